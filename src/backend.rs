@@ -0,0 +1,160 @@
+//! The API that lui speaks to a server.
+//!
+//! Open WebUI ([`crate::server::OpenWebUi`]) is the default and the
+//! only backend with a file store for RAG.  The `openai` backend
+//! ([`crate::openai::OpenAi`]) talks to any OpenAI-compatible endpoint
+//! (llama.cpp's server, vLLM, etc.) directly, without Open WebUI in
+//! between, and the `ollama` backend ([`crate::ollama::Ollama`]) talks
+//! to Ollama's native API.
+
+use serde::Deserialize;
+use serde_json::Value;
+use ureq::BodyReader;
+
+use crate::context::{Context, RagFile};
+use crate::prompt::Prompt;
+use crate::server::{FileRef, OutputReader};
+
+/// The `backend` setting of a server profile.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+pub enum BackendKind {
+    #[default]
+    #[serde(rename = "open-webui")]
+    OpenWebUi,
+    #[serde(rename = "openai")]
+    OpenAi,
+    #[serde(rename = "ollama")]
+    Ollama,
+}
+
+/// Chat, model listing, and (optionally) file operations against a
+/// server.
+///
+/// The file operations default to an error, so a backend without a
+/// file store only has to implement the chat and model methods.
+pub trait Backend {
+    /// The value of `backend` that selects this backend, for messages.
+    fn name(&self) -> &'static str;
+
+    /// Sends a prompt and a context to the model.
+    ///
+    /// Returns a streaming `OutputReader` (`Streamed` for server-sent
+    /// events, `Chunked` for NDJSON) if `stream` is true and an
+    /// `OutputReader::Complete` otherwise.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the request fails or the
+    /// response is malformed.
+    fn send(
+        &self,
+        prompt: &Prompt,
+        context: &Context,
+        files: &[FileRef],
+        stream: bool,
+    ) -> Result<OutputReader<BodyReader<'static>>, String>;
+
+    /// Lists the models the server offers, as raw JSON entries (see
+    /// [`crate::server::model_info`]).
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the request fails or the
+    /// response is malformed.
+    fn list_models(&self) -> Result<Vec<Value>, String>;
+
+    /// Looks up the model with the given ID among
+    /// [`Backend::list_models`].
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if listing the models fails or no
+    /// model has the ID.
+    fn find_model(&self, id: &str) -> Result<Value, String> {
+        self.list_models()?
+            .into_iter()
+            .find(|model| model["id"].as_str() == Some(id))
+            .ok_or_else(|| format!("model '{id}' not found"))
+    }
+
+    /// Whether the backend can store files for RAG.  If this is false,
+    /// the file operations below always fail.
+    fn has_file_store(&self) -> bool {
+        false
+    }
+
+    /// Uploads the RAG `file` to the server's file store under its
+    /// [`name`](RagFile::name) and returns the ID the server assigned
+    /// to it.
+    ///
+    /// If `background` is false, the upload returns once the file has
+    /// been indexed.  Otherwise, it returns right away, and
+    /// [`Backend::indexing_status`] tells when the file is indexed.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the backend has no file store or
+    /// the upload fails.
+    fn upload_file(
+        &self,
+        file: &RagFile,
+        background: bool,
+    ) -> Result<String, String> {
+        let _ = (file, background);
+
+        Err(no_file_store(self.name()))
+    }
+
+    /// Reports whether the server has indexed the file with the given
+    /// ID, after an upload in the background.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the backend has no file store or
+    /// the request fails.
+    fn indexing_status(&self, id: &str) -> Result<Indexing, String> {
+        let _ = id;
+
+        Err(no_file_store(self.name()))
+    }
+
+    /// Deletes the file with the given ID from the server.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the backend has no file store or
+    /// the deletion fails.
+    fn delete_file(&self, id: &str) -> Result<(), String> {
+        let _ = id;
+
+        Err(no_file_store(self.name()))
+    }
+
+    /// Lists the IDs of every file the user can access on the server.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the backend has no file store or
+    /// the listing fails.
+    fn list_files(&self) -> Result<Vec<String>, String> {
+        Err(no_file_store(self.name()))
+    }
+}
+
+/// How far the server has got with indexing an uploaded file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Indexing {
+    Pending,
+    Done,
+    /// Indexing failed, with the server's reason (e.g., no text could be
+    /// extracted from the file).
+    Failed(String),
+}
+
+/// The error for a file operation on a backend without a file store.
+pub fn no_file_store(backend: &str) -> String {
+    format!(
+        "the {backend} backend has no file store, so RAG (-r/--rag) and \
+         file pruning are not available; use backend = \"open-webui\""
+    )
+}
@@ -0,0 +1,314 @@
+//! RAG sources that are not files on disk: `-r -` reads stdin, `-r
+//! cmd:<command>` runs a shell command, and `-r http(s)://...` fetches
+//! a web page or document.
+//!
+//! A multipart upload needs a file of known length, so the content is
+//! first written to a temporary file, which is removed again once the
+//! [`RagFile`] is dropped.  The file is uploaded under a synthetic name
+//! (e.g., `stdin.txt`), and cited by a label that says where the
+//! content came from (e.g., `cmd:journalctl -b`).
+//!
+//! The temporary files are gone by the time lui waits for the model.
+//! Only if lui is killed while it uploads them, e.g., by Ctrl-C, which
+//! lui doesn't handle until then, are they left behind, in a directory
+//! only the user can read.
+
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::context::{RagFile, extension_for_mime, sniff_mime};
+
+/// The prefix of a shell command given as a RAG source.
+const COMMAND_PREFIX: &str = "cmd:";
+
+/// The longest synthetic name, without the extension, that a command
+/// or a URL is uploaded under.
+const MAX_NAME_LEN: usize = 80;
+
+/// A temporary directory that holds a captured source, and is removed
+/// when dropped.
+///
+/// The directory gets a random name and is created fresh, readable only
+/// by the user, so that other users of a shared temporary directory can
+/// neither read the captured content nor plant a file or a symbolic
+/// link where it is written.
+#[derive(Debug)]
+pub struct Scratch(tempfile::TempDir);
+
+impl Scratch {
+    fn new() -> Result<Self, String> {
+        let mut builder = tempfile::Builder::new();
+        builder.prefix("lui-rag-");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            builder.permissions(std::fs::Permissions::from_mode(0o700));
+        }
+
+        builder
+            .tempdir()
+            .map(Self)
+            .map_err(|x| format!("temporary directory: {x}"))
+    }
+}
+
+/// Returns whether `pattern` is a source that [`capture`] handles
+/// rather than a glob pattern.
+pub fn is_capture(pattern: &str) -> bool {
+    pattern == "-"
+        || pattern.starts_with(COMMAND_PREFIX)
+        || is_url(pattern)
+}
+
+fn is_url(pattern: &str) -> bool {
+    pattern.starts_with("https://") || pattern.starts_with("http://")
+}
+
+/// Captures the content of the source `pattern` (see [`is_capture`])
+/// in a temporary file.
+///
+/// # Errors
+///
+/// This function returns an error if stdin cannot be read, the command
+/// cannot be run or fails, the URL cannot be fetched, or the temporary
+/// file cannot be written.
+pub fn capture(pattern: &str) -> Result<RagFile, String> {
+    if pattern == "-" {
+        log::debug!("reading a RAG file from stdin");
+
+        let content = read_all(std::io::stdin(), "stdin")?;
+
+        return store(pattern, "stdin", None, &content);
+    }
+
+    if let Some(command) = pattern.strip_prefix(COMMAND_PREFIX) {
+        return store(pattern, &slug(command), None, &run(command)?);
+    }
+
+    let (content, mime) = fetch(pattern)?;
+
+    store(pattern, &url_name(pattern), mime.as_deref(), &content)
+}
+
+/// Writes `content` to a new [`Scratch`] directory as a file named
+/// after `name`, with the extension of the `mime` type if it has one,
+/// and of the type the content looks like otherwise.
+fn store(
+    label: &str,
+    name: &str,
+    mime: Option<&str>,
+    content: &[u8],
+) -> Result<RagFile, String> {
+    let extension = mime
+        .and_then(extension_for_mime)
+        .or_else(|| extension_for_mime(sniff_mime(content)?))
+        .unwrap_or("txt");
+
+    let name = if Path::new(name)
+        .extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case(extension))
+    {
+        name.to_string()
+    } else {
+        format!("{name}.{extension}")
+    };
+
+    let scratch = Scratch::new()?;
+    let basename = name.rsplit('/').next().unwrap_or(&name);
+    let path = scratch.0.path().join(basename);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        options.mode(0o600);
+    }
+
+    options
+        .open(&path)
+        .and_then(|mut x| x.write_all(content))
+        .map_err(|x| format!("{label}: {x}"))?;
+
+    Ok(RagFile {
+        path,
+        name,
+        label: label.to_string(),
+        _scratch: Some(scratch),
+    })
+}
+
+/// Runs `command` with `sh -c` and returns its standard output.
+/// Standard error is inherited so that the user sees the command's
+/// errors.
+fn run(command: &str) -> Result<Vec<u8>, String> {
+    log::debug!("running {command:?} for a RAG file");
+
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .map_err(|x| format!("{COMMAND_PREFIX}{command}: {x}"))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "{COMMAND_PREFIX}{command}: exited with {}",
+            output.status
+        ));
+    }
+
+    Ok(output.stdout)
+}
+
+/// Fetches `url` and returns the body and the essence of its
+/// `Content-Type` (e.g., `text/html`), if the response has one.
+fn fetch(url: &str) -> Result<(Vec<u8>, Option<String>), String> {
+    log::debug!("fetching {url} for a RAG file");
+
+    let response =
+        ureq::get(url).call().map_err(|x| format!("{url}: {x}"))?;
+
+    let mime = response
+        .headers()
+        .get("content-type")
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.split(';').next())
+        .map(|x| x.trim().to_ascii_lowercase());
+
+    let content = read_all(response.into_body().into_reader(), url)?;
+
+    Ok((content, mime))
+}
+
+fn read_all(
+    mut reader: impl Read,
+    label: &str,
+) -> Result<Vec<u8>, String> {
+    let mut content = Vec::new();
+
+    reader
+        .read_to_end(&mut content)
+        .map_err(|x| format!("{label}: {x}"))?;
+
+    Ok(content)
+}
+
+/// Reduces `text` to a name for the upload: runs of anything but ASCII
+/// letters, digits, `-`, and `.` become one `_`, and the result is cut
+/// off at [`MAX_NAME_LEN`] characters.
+fn slug(text: &str) -> String {
+    let mut result = String::new();
+
+    for c in text.trim().chars() {
+        if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+            result.push(c);
+        } else if !result.ends_with('_') {
+            result.push('_');
+        }
+    }
+
+    result.truncate(MAX_NAME_LEN);
+
+    match result.trim_matches(['_', '.']) {
+        "" => "output".to_string(),
+        x => x.to_string(),
+    }
+}
+
+/// Derives the name to upload the content of `url` under from its host
+/// and path, with `/` between the components, like a relative path
+/// (see [`upload_name`](crate::context::upload_name)).
+fn url_name(url: &str) -> String {
+    let rest = url.split_once("://").map_or(url, |(_, x)| x);
+    let rest = rest.split(['?', '#']).next().unwrap_or(rest);
+
+    let name = rest
+        .split('/')
+        .filter(|x| !x.is_empty())
+        .map(slug)
+        .collect::<Vec<_>>()
+        .join("/");
+
+    if name.is_empty() {
+        "page".to_string()
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_capture_recognizes_stdin_commands_and_urls() {
+        assert!(is_capture("-"));
+        assert!(is_capture("cmd:journalctl -b"));
+        assert!(is_capture("https://example.com/a.pdf"));
+        assert!(!is_capture("docs/*.md"));
+        assert!(!is_capture("./-"));
+    }
+
+    #[test]
+    fn slug_and_url_name_make_readable_names() {
+        assert_eq!(
+            slug("journalctl -b  -u nginx"),
+            "journalctl_-b_-u_nginx"
+        );
+        assert_eq!(slug("  ???  "), "output");
+        assert_eq!(
+            url_name("https://example.com/docs/api.html?v=2#auth"),
+            "example.com/docs/api.html"
+        );
+        assert_eq!(url_name("https://example.com/"), "example.com");
+    }
+
+    #[test]
+    fn store_names_the_file_by_its_type() {
+        let file =
+            store("cmd:make", "make", None, b"cc -c main.c\n").unwrap();
+
+        assert_eq!(file.name, "make.txt");
+        assert_eq!(file.label, "cmd:make");
+        assert_eq!(
+            std::fs::read(&file.path).unwrap(),
+            b"cc -c main.c\n"
+        );
+
+        // Only the user can read the captured content.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = |x: &Path| {
+                std::fs::metadata(x).unwrap().permissions().mode()
+                    & 0o777
+            };
+
+            assert_eq!(mode(&file.path), 0o600);
+            assert_eq!(mode(file.path.parent().unwrap()), 0o700);
+        }
+
+        let pdf = store(
+            "https://example.com/report",
+            "example.com/report",
+            Some("application/pdf"),
+            b"%PDF-1.7",
+        )
+        .unwrap();
+
+        assert_eq!(pdf.name, "example.com/report.pdf");
+        assert!(pdf.path.ends_with("report.pdf"));
+
+        // The temporary file goes away with the `RagFile`.
+        let path = file.path.clone();
+        drop(file);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,321 @@
+//! The conversation state and slash commands of `--chat` mode.
+//!
+//! The REPL itself lives in `main`, next to the single-shot code path
+//! it shares the request and output handling with.  This module only
+//! keeps track of the turns and parses what the user types.  The turns
+//! are kept in a [`Session`], so that the conversation can be continued
+//! later with `--continue` or `--session`.
+
+use std::path::PathBuf;
+
+use crate::prompt::Prompt;
+use crate::server::{ContentPart, Message, MessageContent};
+use crate::session::Session;
+
+/// What the user typed at the chat prompt.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// A question for the model.
+    Ask(String),
+    /// `/add <glob>`: include more files in the context.
+    Add(String),
+    /// `/rag <glob>`: upload more files for RAG, or with `/rag-full`,
+    /// to give the model in full (see `--rag-full`).
+    Rag { pattern: String, full: bool },
+    /// `/model <id>`: switch models.
+    Model(String),
+    /// `/system [text]`: set the system prompt, or clear it.
+    System(Option<String>),
+    /// `/save <file>`: write the conversation as Markdown.
+    Save(PathBuf),
+    /// `/undo`: forget the last question and answer.
+    Undo,
+    /// `/reset`: start a new session.
+    Reset,
+    /// `/help`: list the commands.
+    Help,
+    /// `/quit` or `/exit`: end the session.
+    Quit,
+}
+
+/// The help text for `/help`.
+pub const HELP: &str = "\
+/add <glob>       include more files in the context
+/rag <glob>       upload more files for RAG
+/rag-full <glob>  upload more files for RAG, given to the model in full
+/model <id>       switch to another model
+/system [text]    set the system prompt, or clear it
+/save <file>      write the conversation to a Markdown file
+/undo             forget the last question and answer
+/reset            start a new session (keeps the context)
+/quit             end the session (also Ctrl-D)";
+
+/// Parses a line typed at the chat prompt.  Returns `None` for a blank
+/// line.  A line starting with `//` is a question that starts with `/`.
+///
+/// # Errors
+///
+/// This function returns an error for an unknown command or a command
+/// that is missing its argument.
+pub fn parse_command(line: &str) -> Result<Option<Command>, String> {
+    let line = line.trim();
+
+    if line.is_empty() {
+        return Ok(None);
+    }
+
+    if let Some(rest) = line.strip_prefix("//") {
+        return Ok(Some(Command::Ask(format!("/{rest}"))));
+    }
+
+    let Some(rest) = line.strip_prefix('/') else {
+        return Ok(Some(Command::Ask(line.to_string())));
+    };
+
+    let (name, argument) = match rest.split_once(char::is_whitespace) {
+        Some((name, argument)) => (name, Some(argument.trim())),
+        None => (rest, None),
+    };
+    let argument = argument.filter(|x| !x.is_empty());
+
+    let required = |usage: &str| {
+        argument
+            .map(str::to_string)
+            .ok_or_else(|| format!("usage: /{name} {usage}"))
+    };
+
+    let command = match name {
+        "add" => Command::Add(required("<glob>")?),
+        "rag" | "rag-full" => Command::Rag {
+            pattern: required("<glob>")?,
+            full: name == "rag-full",
+        },
+        "model" => Command::Model(required("<id>")?),
+        "system" => Command::System(argument.map(str::to_string)),
+        "save" => Command::Save(PathBuf::from(required("<file>")?)),
+        "undo" => Command::Undo,
+        "reset" => Command::Reset,
+        "help" => Command::Help,
+        "quit" | "exit" => Command::Quit,
+        _ => {
+            return Err(format!(
+                "unknown command '/{name}'; type /help for a list"
+            ));
+        }
+    };
+
+    Ok(Some(command))
+}
+
+/// A conversation: the prompt it started from and the session that
+/// holds the questions and answers exchanged since.
+pub struct Chat {
+    /// The system prompt, model, parameters, and history (`-H`) that
+    /// every turn is sent with.
+    pub prompt: Prompt,
+
+    /// The turns so far, including those of earlier runs if the
+    /// session was continued.
+    pub session: Session,
+
+    /// With `--save-chat`, the Open WebUI chat that the turns are also
+    /// saved to, or `Some(None)` until the first turn creates it.
+    pub web_chat: Option<Option<String>>,
+}
+
+impl Chat {
+    pub fn new(prompt: Prompt, session: Session) -> Self {
+        Self {
+            prompt,
+            session,
+            web_chat: None,
+        }
+    }
+
+    /// The prompt that asks `question` after the turns so far.
+    pub fn prompt_for(&self, question: &str) -> Prompt {
+        self.session.continue_prompt(&Prompt {
+            question: question.to_string(),
+            ..self.prompt.clone()
+        })
+    }
+
+    /// Forgets the last turn.  Returns false if there was none.
+    pub fn undo(&mut self) -> bool {
+        self.session.exchanges.pop().is_some()
+    }
+
+    /// Leaves the turns so far in their session, and their Open WebUI
+    /// chat if any, and starts new ones.
+    pub fn reset(&mut self) {
+        self.session = self.session.restart();
+
+        if self.web_chat.is_some() {
+            self.web_chat = Some(None);
+        }
+    }
+
+    /// The conversation as Markdown: the system prompt, if any, then
+    /// each message under a heading that names its role.
+    pub fn transcript(&self) -> String {
+        let mut result = String::new();
+
+        if let Some(ref system) = self.prompt.system {
+            result.push_str(&format!("## system\n\n{system}\n\n"));
+        }
+
+        let history = self.prompt.history.iter().flatten();
+        let turns = self.session.history();
+
+        for message in history.chain(&turns) {
+            let text = message_text(message);
+            let text =
+                text.strip_prefix("#Prompt\n\n").unwrap_or(&text);
+
+            result.push_str(&format!(
+                "## {}\n\n{}\n\n",
+                message.role,
+                text.trim_end()
+            ));
+        }
+
+        result
+    }
+}
+
+/// The text parts of a message, joined, with a placeholder for each
+/// image.
+fn message_text(message: &Message) -> String {
+    match &message.content {
+        MessageContent::Text(text) => text.clone(),
+        MessageContent::Parts(parts) => parts
+            .iter()
+            .map(|part| match part {
+                ContentPart::Text { text } => text.as_str(),
+                ContentPart::ImageUrl { .. } => "(image)",
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt::Params;
+    use crate::session::Exchange;
+
+    fn test_chat() -> Chat {
+        Chat::new(
+            Prompt {
+                label: String::new(),
+                history: None,
+                system: Some("Be brief.".to_string()),
+                question: String::new(),
+                model: Some("m".to_string()),
+                params: Params::new(),
+                schema: None,
+            },
+            Session::create(&std::env::temp_dir()),
+        )
+    }
+
+    #[test]
+    fn parse_command_recognizes_commands() {
+        let parse = |x| parse_command(x).unwrap();
+
+        assert_eq!(parse("  "), None);
+        assert_eq!(
+            parse("what is this?"),
+            Some(Command::Ask("what is this?".to_string()))
+        );
+        assert_eq!(
+            parse("//etc/hosts?"),
+            Some(Command::Ask("/etc/hosts?".to_string()))
+        );
+        assert_eq!(
+            parse("/add src/*.rs"),
+            Some(Command::Add("src/*.rs".to_string()))
+        );
+        assert_eq!(
+            parse("/model qwen3:32b"),
+            Some(Command::Model("qwen3:32b".to_string()))
+        );
+        assert_eq!(parse("/system"), Some(Command::System(None)));
+        assert_eq!(
+            parse("/system Answer in French."),
+            Some(Command::System(Some(
+                "Answer in French.".to_string()
+            )))
+        );
+        assert_eq!(
+            parse("/save notes.md"),
+            Some(Command::Save(PathBuf::from("notes.md")))
+        );
+        assert_eq!(parse("/undo"), Some(Command::Undo));
+        assert_eq!(parse("/exit"), Some(Command::Quit));
+
+        assert_eq!(
+            parse("/rag-full docs/*.md"),
+            Some(Command::Rag {
+                pattern: "docs/*.md".to_string(),
+                full: true,
+            })
+        );
+
+        assert!(parse_command("/rag").is_err());
+        assert!(parse_command("/frobnicate").is_err());
+    }
+
+    #[test]
+    fn chat_replays_turns_as_history() {
+        let mut chat = test_chat();
+
+        let first = chat.prompt_for("Who wrote it?");
+        assert_eq!(first.history, None);
+        chat.session.exchanges.push(Exchange::new(
+            &first,
+            Vec::new(),
+            Vec::new(),
+            "Ada.",
+        ));
+
+        let second = chat.prompt_for("When?");
+        let roles: Vec<String> =
+            second.as_messages().into_iter().map(|x| x.role).collect();
+        assert_eq!(roles, vec!["system", "user", "assistant", "user"]);
+        chat.session.exchanges.push(Exchange::new(
+            &second,
+            Vec::new(),
+            Vec::new(),
+            "In 1843.",
+        ));
+        assert_eq!(chat.session.exchanges.len(), 2);
+
+        assert!(chat.undo());
+        assert_eq!(chat.session.exchanges.len(), 1);
+
+        chat.reset();
+        assert!(!chat.undo());
+        assert_eq!(chat.prompt_for("Hi").history, None);
+    }
+
+    #[test]
+    fn transcript_lists_messages_by_role() {
+        let mut chat = test_chat();
+        let prompt = chat.prompt_for("Who wrote it?");
+        chat.session.exchanges.push(Exchange::new(
+            &prompt,
+            Vec::new(),
+            Vec::new(),
+            "Ada.\n",
+        ));
+
+        assert_eq!(
+            chat.transcript(),
+            "## system\n\nBe brief.\n\n\
+             ## user\n\nWho wrote it?\n\n\
+             ## assistant\n\nAda.\n\n"
+        );
+    }
+}
@@ -0,0 +1,585 @@
+//! Open WebUI's chat history: saving conversations held with lui, so
+//! that they show up in the web UI and can be continued there
+//! (`--save-chat`), and importing chats as history (`--from-chat`).
+//!
+//! Open WebUI stores a chat as a tree of messages: `history.messages`
+//! maps message IDs to messages that point at their parent and
+//! children, and `history.currentId` is the last message of the branch
+//! that the web UI shows.  The chat also carries that branch as a flat
+//! `messages` list.  Lui only ever extends the current branch, and
+//! keeps both in step.  It also imports only the current branch.
+
+use base64::prelude::{BASE64_STANDARD, Engine as _};
+use serde_json::{Value, json};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::context::image_mime;
+use crate::server::{
+    ContentPart, ImageUrl, Message, MessageContent, Server,
+};
+
+/// How many characters of the first question to use as the title of a
+/// new chat.
+const TITLE_MAX_CHARS: usize = 60;
+
+/// A message to add to a chat.
+#[derive(Debug)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+
+    /// The model that wrote an assistant message, or that a user
+    /// message was sent to.
+    pub model: Option<String>,
+
+    /// Attached images (`{"type": "image", "url": ...}`) and RAG files
+    /// (`{"type": "file", "id": ..., "name": ...}`).
+    pub files: Vec<Value>,
+}
+
+impl ChatMessage {
+    /// Converts a message as sent to the model.  Text parts are joined,
+    /// and image parts become image attachments.  The `#Prompt` heading
+    /// that lui puts before each question is removed.
+    pub fn from_message(
+        message: &Message,
+        model: Option<&str>,
+    ) -> Self {
+        let (text, images) = match &message.content {
+            MessageContent::Text(text) => (text.clone(), Vec::new()),
+            MessageContent::Parts(parts) => {
+                let mut texts = Vec::new();
+                let mut images = Vec::new();
+
+                for part in parts {
+                    match part {
+                        ContentPart::Text { text } => {
+                            texts.push(text.as_str())
+                        }
+                        ContentPart::ImageUrl { image_url } => images.push(
+                            json!({ "type": "image", "url": image_url.url }),
+                        ),
+                    }
+                }
+
+                (texts.join("\n\n"), images)
+            }
+        };
+
+        let content = text
+            .strip_prefix("#Prompt\n\n")
+            .map_or(text.clone(), str::to_string);
+
+        Self {
+            role: message.role.clone(),
+            content,
+            model: model.map(str::to_string),
+            files: images,
+        }
+    }
+}
+
+/// Returns the URL at which the web UI shows the chat `id`.
+///
+/// # Errors
+///
+/// This function returns an error if the server's address is
+/// misconfigured (see [`Server::url`]).
+pub fn chat_url(server: &Server, id: &str) -> Result<String, String> {
+    server.url(&format!("/c/{id}"))
+}
+
+/// Fetches the chat `id`, and returns its `chat` object.
+///
+/// # Errors
+///
+/// This function returns an error if the ID is not safe to use in a
+/// URL, the request fails, or the response has no `chat` object.
+pub fn get(server: &Server, id: &str) -> Result<Value, String> {
+    check_id(id)?;
+
+    let value: Value = server
+        .agent()?
+        .get(&server.url(&format!("/api/v1/chats/{id}"))?)
+        .header("Authorization", &server.bearer()?)
+        .call()
+        .map_err(|x| format!("chat {id}: {x}"))?
+        .into_body()
+        .read_json()
+        .map_err(|x| format!("chat {id}: {x}"))?;
+
+    match value.get("chat") {
+        Some(chat) if chat.is_object() => Ok(chat.clone()),
+        _ => Err(format!("chat {id}: malformed response")),
+    }
+}
+
+/// Saves `messages` as a new chat, titled after its first question.
+/// Returns the ID that Open WebUI gave it.
+///
+/// # Errors
+///
+/// This function returns an error if the request fails or the response
+/// has no ID.
+pub fn create(
+    server: &Server,
+    system: Option<&str>,
+    messages: &[ChatMessage],
+) -> Result<String, String> {
+    let title = messages
+        .iter()
+        .find(|x| x.role == "user")
+        .map(|x| crate::abbreviate(&x.content, TITLE_MAX_CHARS))
+        .unwrap_or_else(|| "lui".to_string());
+
+    let models: Vec<&str> =
+        messages.iter().filter_map(|x| x.model.as_deref()).collect();
+
+    let mut chat = json!({
+        "title": title,
+        "models": models.last().map(|x| vec![*x]).unwrap_or_default(),
+        "params": {},
+        "history": { "messages": {}, "currentId": null },
+        "messages": [],
+        "tags": [],
+        "files": [],
+        "timestamp": now() * 1000,
+    });
+
+    if let Some(system) = system {
+        chat["system"] = json!(system);
+        chat["params"]["system"] = json!(system);
+    }
+
+    for message in messages {
+        append(&mut chat, message);
+    }
+
+    let value: Value = server
+        .agent()?
+        .post(&server.url("/api/v1/chats/new")?)
+        .header("Authorization", &server.bearer()?)
+        .send_json(json!({ "chat": chat }))
+        .map_err(|x| format!("{x}"))?
+        .into_body()
+        .read_json()
+        .map_err(|x| format!("{x}"))?;
+
+    value["id"]
+        .as_str()
+        .filter(|x| crate::server::is_safe_id(x))
+        .map(str::to_string)
+        .ok_or_else(|| "malformed response to a new chat".to_string())
+}
+
+/// Adds `messages` to the end of the chat `id`.
+///
+/// # Errors
+///
+/// This function returns an error if the chat cannot be fetched or
+/// updated.
+pub fn extend(
+    server: &Server,
+    id: &str,
+    messages: &[ChatMessage],
+) -> Result<(), String> {
+    let mut chat = get(server, id)?;
+
+    for message in messages {
+        append(&mut chat, message);
+    }
+
+    server
+        .agent()?
+        .post(&server.url(&format!("/api/v1/chats/{id}"))?)
+        .header("Authorization", &server.bearer()?)
+        .send_json(json!({ "chat": chat }))
+        .map_err(|x| format!("chat {id}: {x}"))?;
+
+    Ok(())
+}
+
+/// Lists the user's chats, most recently updated first, as the raw
+/// entries (`id`, `title`, `updated_at`, `created_at`).
+///
+/// # Errors
+///
+/// This function returns an error if the request fails or the response
+/// is not a JSON array.
+pub fn list(server: &Server) -> Result<Vec<Value>, String> {
+    let value: Value = server
+        .agent()?
+        .get(&server.url("/api/v1/chats/")?)
+        .header("Authorization", &server.bearer()?)
+        .call()
+        .map_err(|x| format!("{x}"))?
+        .into_body()
+        .read_json()
+        .map_err(|x| format!("{x}"))?;
+
+    value
+        .as_array()
+        .cloned()
+        .ok_or_else(|| "malformed chat list".to_string())
+}
+
+/// Converts the current branch of `chat` into messages for
+/// [`crate::prompt::Prompt::history`].  Attached images become
+/// `image_url` parts.  An image stored on the server, with a URL that
+/// is a path, is downloaded and inlined as a `data:` URL, as `-i` does,
+/// because the model's side cannot fetch it without lui's API key.
+///
+/// Messages that are neither from the user, the assistant, nor the
+/// system are skipped, and so are empty ones (e.g., an answer that was
+/// stopped before its first token).
+///
+/// # Errors
+///
+/// This function returns an error if an image stored on the server
+/// cannot be downloaded, or is not in a supported format.
+pub fn history(
+    chat: &Value,
+    server: &Server,
+) -> Result<Vec<Message>, String> {
+    convert_branch(chat, |path| download_image(server, path))
+}
+
+/// Does the work of [`history`], with `download` turning the path of
+/// an image stored on the server into a `data:` URL.
+fn convert_branch(
+    chat: &Value,
+    mut download: impl FnMut(&str) -> Result<String, String>,
+) -> Result<Vec<Message>, String> {
+    let mut messages = Vec::new();
+
+    for message in current_branch(chat) {
+        let Some(role) = message["role"].as_str() else {
+            continue;
+        };
+
+        if !matches!(role, "user" | "assistant" | "system") {
+            continue;
+        }
+
+        let text = message["content"].as_str().unwrap_or_default();
+        let mut images = Vec::new();
+
+        for url in message["files"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|x| x["type"] == "image")
+            .filter_map(|x| x["url"].as_str())
+        {
+            let url = if url.starts_with('/') {
+                download(url)?
+            } else {
+                url.to_string()
+            };
+
+            images.push(ContentPart::ImageUrl {
+                image_url: ImageUrl { url },
+            });
+        }
+
+        let content = if images.is_empty() {
+            if text.trim().is_empty() {
+                continue;
+            }
+
+            MessageContent::Text(text.to_string())
+        } else {
+            let mut parts = vec![ContentPart::Text {
+                text: text.to_string(),
+            }];
+            parts.extend(images);
+
+            MessageContent::Parts(parts)
+        };
+
+        messages.push(Message {
+            role: role.to_string(),
+            content,
+        });
+    }
+
+    Ok(messages)
+}
+
+/// Downloads the image at `path` on the server and returns it as a
+/// `data:` URL.
+fn download_image(
+    server: &Server,
+    path: &str,
+) -> Result<String, String> {
+    log::debug!("downloading image {path}");
+
+    let bytes = server
+        .agent()?
+        .get(&server.url(path)?)
+        .header("Authorization", &server.bearer()?)
+        .call()
+        .map_err(|x| format!("image {path}: {x}"))?
+        .into_body()
+        .read_to_vec()
+        .map_err(|x| format!("image {path}: {x}"))?;
+
+    let mime = image_mime(&bytes).ok_or_else(|| {
+        format!("image {path}: not a supported image format")
+    })?;
+
+    Ok(format!(
+        "data:{mime};base64,{}",
+        BASE64_STANDARD.encode(&bytes)
+    ))
+}
+
+/// Returns the messages of the branch that ends at
+/// `history.currentId`, from the first.  Falls back to the flat
+/// `messages` list for a chat without a usable tree.
+fn current_branch(chat: &Value) -> Vec<&Value> {
+    let nodes = &chat["history"]["messages"];
+    let mut branch = Vec::new();
+    let mut next = chat["history"]["currentId"].as_str();
+
+    while let Some(id) = next {
+        let node = &nodes[id];
+
+        // A dangling ID, or a cycle, means the tree is unusable.
+        if node.is_null()
+            || branch.len() > nodes.as_object().map_or(0, |x| x.len())
+        {
+            branch.clear();
+            break;
+        }
+
+        branch.push(node);
+        next = node["parentId"].as_str();
+    }
+
+    if branch.is_empty() {
+        return chat["messages"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .collect();
+    }
+
+    branch.reverse();
+
+    branch
+}
+
+/// Appends `message` to the current branch of `chat`.
+fn append(chat: &mut Value, message: &ChatMessage) {
+    let id = uuid::Uuid::new_v4().to_string();
+    let parent = chat["history"]["currentId"].clone();
+
+    let mut node = json!({
+        "id": id,
+        "parentId": parent,
+        "childrenIds": [],
+        "role": message.role,
+        "content": message.content,
+        "timestamp": now(),
+    });
+
+    if let Some(ref model) = message.model {
+        if message.role == "assistant" {
+            node["model"] = json!(model);
+            node["done"] = json!(true);
+        } else {
+            node["models"] = json!([model]);
+        }
+    }
+
+    if !message.files.is_empty() {
+        node["files"] = json!(message.files);
+    }
+
+    if let Some(parent) = parent.as_str()
+        && let Some(children) =
+            chat["history"]["messages"][parent]["childrenIds"]
+                .as_array_mut()
+    {
+        children.push(json!(id));
+    }
+
+    if !chat["history"]["messages"].is_object() {
+        chat["history"]["messages"] = json!({});
+    }
+
+    chat["history"]["messages"][&id] = node.clone();
+    chat["history"]["currentId"] = json!(id);
+
+    match chat["messages"].as_array_mut() {
+        Some(messages) => messages.push(node),
+        None => chat["messages"] = json!([node]),
+    }
+}
+
+/// Checks that `id` can be put in a URL path.
+///
+/// # Errors
+///
+/// This function returns an error if it cannot.
+fn check_id(id: &str) -> Result<(), String> {
+    if crate::server::is_safe_id(id) {
+        Ok(())
+    } else {
+        Err(format!("invalid chat ID {id:?}"))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(role: &str, content: &str) -> ChatMessage {
+        ChatMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+            model: Some("m".to_string()),
+            files: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn append_links_messages_into_a_branch() {
+        let mut chat = json!({
+            "history": { "messages": {}, "currentId": null },
+            "messages": [],
+        });
+
+        append(&mut chat, &text("user", "Who?"));
+        append(&mut chat, &text("assistant", "Ada."));
+
+        let messages = chat["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 2);
+
+        let question = messages[0]["id"].as_str().unwrap();
+        let answer = messages[1]["id"].as_str().unwrap();
+
+        assert!(messages[0]["parentId"].is_null());
+        assert_eq!(messages[0]["models"], json!(["m"]));
+        assert_eq!(messages[1]["parentId"], question);
+        assert_eq!(messages[1]["model"], "m");
+        assert_eq!(chat["history"]["currentId"], answer);
+        assert_eq!(
+            chat["history"]["messages"][question]["childrenIds"],
+            json!([answer])
+        );
+    }
+
+    #[test]
+    fn from_message_moves_images_to_files() {
+        let message = Message {
+            role: "user".to_string(),
+            content: MessageContent::Parts(vec![
+                ContentPart::Text {
+                    text: "#Prompt\n\nWhat is this?".to_string(),
+                },
+                ContentPart::ImageUrl {
+                    image_url: ImageUrl {
+                        url: "data:image/png;base64,AAA".to_string(),
+                    },
+                },
+            ]),
+        };
+
+        let converted = ChatMessage::from_message(&message, None);
+
+        assert_eq!(converted.content, "What is this?");
+        assert_eq!(
+            converted.files,
+            vec![json!({
+                "type": "image",
+                "url": "data:image/png;base64,AAA",
+            })]
+        );
+    }
+
+    #[test]
+    fn history_follows_the_current_branch() {
+        let chat = json!({
+            "history": {
+                "currentId": "a2",
+                "messages": {
+                    "q": {
+                        "id": "q", "parentId": null,
+                        "childrenIds": ["a1", "a2"],
+                        "role": "user", "content": "What is this?",
+                        "files": [
+                            { "type": "image", "url": "data:image/png;base64,AAA" },
+                            { "type": "image", "url": "/api/v1/files/i/content" },
+                            { "type": "file", "id": "f", "name": "x.pdf" },
+                        ],
+                    },
+                    "a1": {
+                        "id": "a1", "parentId": "q", "childrenIds": [],
+                        "role": "assistant", "content": "A cat.",
+                    },
+                    "a2": {
+                        "id": "a2", "parentId": "q", "childrenIds": [],
+                        "role": "assistant", "content": "A dog.",
+                    },
+                },
+            },
+            "messages": [],
+        });
+
+        let mut downloaded = Vec::new();
+        let messages = convert_branch(&chat, |path| {
+            downloaded.push(path.to_string());
+            Ok("data:image/png;base64,BBB".to_string())
+        })
+        .unwrap();
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0].content,
+            MessageContent::Parts(vec![
+                ContentPart::Text {
+                    text: "What is this?".to_string(),
+                },
+                ContentPart::ImageUrl {
+                    image_url: ImageUrl {
+                        url: "data:image/png;base64,AAA".to_string(),
+                    },
+                },
+                ContentPart::ImageUrl {
+                    image_url: ImageUrl {
+                        url: "data:image/png;base64,BBB".to_string(),
+                    },
+                },
+            ])
+        );
+        assert_eq!(downloaded, ["/api/v1/files/i/content"]);
+        assert_eq!(
+            messages[1].content,
+            MessageContent::Text("A dog.".to_string())
+        );
+    }
+
+    #[test]
+    fn history_falls_back_to_the_message_list() {
+        let chat = json!({
+            "messages": [
+                { "role": "user", "content": "Hi" },
+                { "role": "assistant", "content": "" },
+            ],
+        });
+
+        let messages =
+            convert_branch(&chat, |_| unreachable!()).unwrap();
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].role, "user");
+    }
+}
@@ -1,12 +1,23 @@
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
-use crate::prompt::Prompt;
-use crate::server::{Message, Server};
+use crate::prompt::{Params, Prompt};
+use crate::secret;
+use crate::server::{self, Message, RESERVED_PARAMS, Server};
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
-    pub server: Server,
+    /// The unnamed server, used when no profile is selected.
+    pub server: Option<Server>,
+
+    /// Named server profiles, selected with `--server` or
+    /// `default-server`.
+    #[serde(default)]
+    pub servers: BTreeMap<String, Server>,
+
+    #[serde(rename = "default-server")]
+    pub default_server: Option<String>,
 
     #[serde(rename = "default-history")]
     pub default_history: Option<Vec<Message>>,
@@ -20,6 +31,11 @@ pub struct Config {
     #[serde(rename = "default-model")]
     pub default_model: Option<String>,
 
+    /// Parameters for every prompt.  A prompt's own `params` override
+    /// these key by key.
+    #[serde(rename = "default-params", default)]
+    pub default_params: Params,
+
     pub prompt: Vec<Prompt>,
 }
 
@@ -33,28 +49,124 @@ impl Config {
     ///
     /// - the path to the user's configuration file cannot be
     ///   determined,
-    /// - the configuration file doesn't exist, or
-    /// - the configuration file contains a parse error.
+    /// - the configuration file doesn't exist,
+    /// - the configuration file contains a parse error,
+    /// - a server profile's name is not safe to use as a directory
+    ///   name (see [`server::is_safe_id`]), or
+    /// - `default-params` or a prompt's `params` name a field that lui
+    ///   sets itself (see [`Config::check_params`]).
     pub fn load() -> Result<Self, String> {
         let path = get_config_path().ok_or_else(|| {
             "Home directory cannot be determined".to_string()
         })?;
 
-        let config: Config = toml::from_str(
+        let mut config: Config = toml::from_str(
             &std::fs::read_to_string(path.clone())
                 .map_err(|error| format!("{path:?}: {error}"))?,
         )
         .map_err(|error| error.message().to_string())?;
 
+        // The name keys the profile's upload journal, so it becomes a
+        // path component.
+        for (name, server) in config.servers.iter_mut() {
+            if !server::is_safe_id(name) {
+                return Err(format!(
+                    "server name {name:?} may only contain ASCII \
+                     letters, digits, '.', '-', and '_'"
+                ));
+            }
+
+            server.name = Some(name.clone());
+        }
+
+        config.check_params()?;
+
+        if config
+            .server
+            .iter()
+            .chain(config.servers.values())
+            .any(|server| server.api_key.is_some())
+            && let Err(x) = secret::check_private(&path)
+        {
+            log::warn!(
+                "{x}; consider api-key-file, api-key-command, or \
+                 api-key-env instead of api-key"
+            );
+        }
+
         Ok(config)
     }
 
+    /// Checks that neither `default-params` nor the `params` of a
+    /// prompt name one of the [`RESERVED_PARAMS`], which would be sent
+    /// twice, next to the field that lui sets (e.g., `stream`, which
+    /// would override `--no-stream`).
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error naming the first such parameter,
+    /// and the prompt that has it.
+    fn check_params(&self) -> Result<(), String> {
+        let reserved = |params: &Params| {
+            params
+                .keys()
+                .find(|x| RESERVED_PARAMS.contains(&x.as_str()))
+                .cloned()
+        };
+
+        if let Some(key) = reserved(&self.default_params) {
+            return Err(format!(
+                "default-params: parameter {key:?} is set by lui \
+                 itself"
+            ));
+        }
+
+        for prompt in &self.prompt {
+            if let Some(key) = reserved(&prompt.params) {
+                return Err(format!(
+                    "prompt {:?}: parameter {key:?} is set by lui \
+                     itself",
+                    prompt.label
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Picks the server to talk to: the profile named by `name` (from
+    /// `--server`) if given, then the profile named by
+    /// `default-server`, then the unnamed `[server]` table.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the chosen profile doesn't exist,
+    /// or if no profile is chosen and there is no `[server]` table.
+    pub fn resolve_server(
+        &self,
+        name: Option<&str>,
+    ) -> Result<&Server, String> {
+        match name.or(self.default_server.as_deref()) {
+            Some(name) => self
+                .servers
+                .get(name)
+                .ok_or_else(|| format!("server '{name}' not found")),
+            None => self.server.as_ref().ok_or_else(|| {
+                "no server specified; add a [server] table, or select \
+                 one of [servers.<name>] with --server or \
+                 default-server"
+                    .to_string()
+            }),
+        }
+    }
+
     pub fn resolve_prompt(
         &self,
         history: Option<&[Message]>,
         system: Option<&str>,
         question: Option<&str>,
         model: Option<&str>,
+        params: &Params,
     ) -> Result<Prompt, String> {
         if let Some(x) = question
             && !x.starts_with('@')
@@ -64,21 +176,10 @@ impl Config {
             if x.is_empty() {
                 Err("prompt is empty".to_string())
             } else {
-                let history =
-                    history.or(self.default_history.as_deref());
-                let system = system.or(self.default_system.as_deref());
-                let model = model
-                    .or(self.default_model.as_deref())
-                    .ok_or_else(|| {
-                        "no default model specified".to_string()
-                    })?;
-
                 Ok(Prompt {
-                    label: String::new(),
-                    history: history.map(Vec::from),
-                    system: system.map(str::to_string),
                     question: x.to_string(),
-                    model: Some(model.to_string()),
+                    ..self
+                        .bare_prompt(history, system, model, params)?
                 })
             }
         } else {
@@ -108,26 +209,102 @@ impl Config {
                 }
             };
 
-            // Use the history, system prompt, and model that the user
-            // has given us instead of the one pre-specified for the
-            // prompt.
-            prompt.history = history
-                .map(Vec::from)
-                .or_else(|| prompt.history.clone())
-                .or_else(|| self.default_history.clone());
-            prompt.system = system
-                .map(str::to_string)
-                .or_else(|| prompt.system.clone())
-                .or_else(|| self.default_system.clone());
-            prompt.model = model
-                .map(str::to_string)
-                .or_else(|| prompt.model.clone())
-                .or_else(|| self.default_model.clone());
+            self.apply_defaults(
+                &mut prompt,
+                history,
+                system,
+                model,
+                params,
+            );
 
             Ok(prompt)
         }
     }
 
+    /// Returns a prompt without a question (and without a label) that
+    /// uses the history, system prompt, model, and parameters the user
+    /// has given us, and the configured defaults for the rest.  This is
+    /// what a plain-text question is asked with.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if neither the user nor the
+    /// configuration specifies a model.
+    pub fn bare_prompt(
+        &self,
+        history: Option<&[Message]>,
+        system: Option<&str>,
+        model: Option<&str>,
+        params: &Params,
+    ) -> Result<Prompt, String> {
+        let history = history.or(self.default_history.as_deref());
+        let system = system.or(self.default_system.as_deref());
+        let model = model
+            .or(self.default_model.as_deref())
+            .ok_or_else(|| "no default model specified".to_string())?;
+
+        Ok(Prompt {
+            label: String::new(),
+            history: history.map(Vec::from),
+            system: system.map(str::to_string),
+            question: String::new(),
+            model: Some(model.to_string()),
+            params: merge_params(&self.default_params, params),
+            schema: None,
+        })
+    }
+
+    /// Returns every configured prompt with the defaults applied, in
+    /// configuration order, as [`Config::resolve_prompt`] would resolve
+    /// it by label.
+    pub fn list_prompts(&self) -> Vec<Prompt> {
+        self.prompt
+            .iter()
+            .map(|prompt| {
+                let mut prompt = prompt.clone();
+                self.apply_defaults(
+                    &mut prompt,
+                    None,
+                    None,
+                    None,
+                    &Params::new(),
+                );
+                prompt
+            })
+            .collect()
+    }
+
+    /// Uses the history, system prompt, model, and parameters that the
+    /// user has given us instead of the ones pre-specified for the
+    /// prompt, and the configured defaults for whatever neither of them
+    /// sets.  Parameters are merged key by key with the same
+    /// precedence.
+    fn apply_defaults(
+        &self,
+        prompt: &mut Prompt,
+        history: Option<&[Message]>,
+        system: Option<&str>,
+        model: Option<&str>,
+        params: &Params,
+    ) {
+        prompt.history = history
+            .map(Vec::from)
+            .or_else(|| prompt.history.clone())
+            .or_else(|| self.default_history.clone());
+        prompt.system = system
+            .map(str::to_string)
+            .or_else(|| prompt.system.clone())
+            .or_else(|| self.default_system.clone());
+        prompt.model = model
+            .map(str::to_string)
+            .or_else(|| prompt.model.clone())
+            .or_else(|| self.default_model.clone());
+        prompt.params = merge_params(
+            &merge_params(&self.default_params, &prompt.params),
+            params,
+        );
+    }
+
     fn find_prompt(&self, label: &str) -> Option<Prompt> {
         for prompt in self.prompt.iter() {
             if prompt.label == label {
@@ -139,6 +316,16 @@ impl Config {
     }
 }
 
+/// Returns `base` with the entries of `overrides` added, replacing any
+/// with the same key.
+fn merge_params(base: &Params, overrides: &Params) -> Params {
+    let mut result = base.clone();
+
+    result.extend(overrides.clone());
+
+    result
+}
+
 /// Constructs the path to the user's configuration file
 /// (`$XDG_CONFIG_HOME/lui/config.toml`).
 ///
@@ -163,6 +350,8 @@ mod tests {
             Prompt {
                 label: "foo".to_string(),
                 model: Some("foo".to_string()),
+                params: Params::new(),
+                schema: None,
                 history: None,
                 system: None,
                 question: "foo bar baz".to_string(),
@@ -170,6 +359,8 @@ mod tests {
             Prompt {
                 label: "bar".to_string(),
                 model: Some("bar".to_string()),
+                params: Params::new(),
+                schema: None,
                 history: None,
                 system: None,
                 question: "bar baz foo".to_string(),
@@ -179,15 +370,14 @@ mod tests {
 
     fn make_config_without_defaults() -> Config {
         Config {
-            server: Server {
-                host: "".to_string(),
-                port: 5000,
-                api_key: "".to_string(),
-            },
+            server: Some(Server::default()),
+            servers: BTreeMap::new(),
+            default_server: None,
             default_history: None,
             default_system: None,
             default_prompt: None,
             default_model: None,
+            default_params: Params::new(),
             prompt: make_prompts(),
         }
     }
@@ -215,6 +405,8 @@ mod tests {
                 history: None,
                 system: None,
                 model: Some("m".to_string()),
+                params: Params::new(),
+                schema: None,
                 question: "...".to_string(),
             })
         };
@@ -224,6 +416,8 @@ mod tests {
                 history: None,
                 system: None,
                 model: Some("um".to_string()),
+                params: Params::new(),
+                schema: None,
                 question: "...".to_string(),
             })
         };
@@ -308,12 +502,110 @@ mod tests {
             config.default_model = defm.map(|x| x.to_string());
 
             assert_eq!(
-                config.resolve_prompt(None, None, *q, *m),
+                config.resolve_prompt(
+                    None,
+                    None,
+                    *q,
+                    *m,
+                    &Params::new()
+                ),
                 *expected
             );
         }
     }
 
+    #[test]
+    fn resolve_server_precedence() {
+        let named = |name: &str| {
+            let mut server = Server::default();
+            server.name = Some(name.to_string());
+            server
+        };
+
+        let mut config = make_config_without_defaults();
+        config.servers.insert("gpu".to_string(), named("gpu"));
+        config
+            .servers
+            .insert("staging".to_string(), named("staging"));
+
+        // Without a choice, the unnamed [server] table is used.
+        assert_eq!(config.resolve_server(None).unwrap().name, None);
+
+        // default-server overrides the unnamed table...
+        config.default_server = Some("gpu".to_string());
+        assert_eq!(
+            config.resolve_server(None).unwrap().name.as_deref(),
+            Some("gpu")
+        );
+
+        // ...and --server overrides default-server.
+        assert_eq!(
+            config
+                .resolve_server(Some("staging"))
+                .unwrap()
+                .name
+                .as_deref(),
+            Some("staging")
+        );
+
+        assert_eq!(
+            config.resolve_server(Some("laptop")).unwrap_err(),
+            "server 'laptop' not found"
+        );
+
+        // With no [server] table and no choice, there is nothing to use.
+        config.server = None;
+        config.default_server = None;
+        assert!(config.resolve_server(None).is_err());
+    }
+
+    #[test]
+    fn servers_deserialize_from_named_tables() {
+        let config: Config = toml::from_str(
+            r#"
+            default-server = "gpu"
+            prompt = []
+
+            [servers.laptop]
+            host = "127.0.0.1"
+            port = 3000
+            api-key = "a"
+
+            [servers.gpu]
+            base-url = "https://gpu.corp/"
+            api-key = "b"
+            "#,
+        )
+        .unwrap();
+
+        assert!(config.server.is_none());
+        assert_eq!(
+            config.servers.keys().collect::<Vec<_>>(),
+            vec!["gpu", "laptop"]
+        );
+        assert_eq!(
+            config.servers["gpu"].base_url.as_deref(),
+            Some("https://gpu.corp/")
+        );
+    }
+
+    #[test]
+    fn list_prompts_applies_defaults() {
+        let mut config = make_config_without_defaults();
+        config.default_model = Some("m".to_string());
+        config.default_system = Some("be brief".to_string());
+        config.prompt[0].model = None;
+        config.prompt[1].system = Some("own".to_string());
+
+        let prompts = config.list_prompts();
+
+        assert_eq!(prompts.len(), 2);
+        assert_eq!(prompts[0].model.as_deref(), Some("m"));
+        assert_eq!(prompts[0].system.as_deref(), Some("be brief"));
+        assert_eq!(prompts[1].model.as_deref(), Some("bar"));
+        assert_eq!(prompts[1].system.as_deref(), Some("own"));
+    }
+
     /// Builds a conversation history from `role:content` strings.
     fn make_history(items: &[&str]) -> Vec<Message> {
         items
@@ -337,7 +629,8 @@ mod tests {
                     Some(&cli_history),
                     None,
                     Some("hi"),
-                    None
+                    None,
+                    &Params::new()
                 )
                 .unwrap()
                 .history,
@@ -348,7 +641,13 @@ mod tests {
         config.default_history = Some(default_history.clone());
         assert_eq!(
             config
-                .resolve_prompt(None, None, Some("hi"), None)
+                .resolve_prompt(
+                    None,
+                    None,
+                    Some("hi"),
+                    None,
+                    &Params::new()
+                )
                 .unwrap()
                 .history,
             Some(default_history)
@@ -361,7 +660,8 @@ mod tests {
                     Some(&cli_history),
                     None,
                     Some("hi"),
-                    None
+                    None,
+                    &Params::new()
                 )
                 .unwrap()
                 .history,
@@ -380,7 +680,13 @@ mod tests {
         config.prompt[0].history = Some(prompt_history.clone());
         assert_eq!(
             config
-                .resolve_prompt(None, None, Some("@foo"), None)
+                .resolve_prompt(
+                    None,
+                    None,
+                    Some("@foo"),
+                    None,
+                    &Params::new()
+                )
                 .unwrap()
                 .history,
             Some(prompt_history)
@@ -393,7 +699,8 @@ mod tests {
                     Some(&cli_history),
                     None,
                     Some("@foo"),
-                    None
+                    None,
+                    &Params::new()
                 )
                 .unwrap()
                 .history,
@@ -406,10 +713,140 @@ mod tests {
         config.default_history = Some(default_history.clone());
         assert_eq!(
             config
-                .resolve_prompt(None, None, Some("@foo"), None)
+                .resolve_prompt(
+                    None,
+                    None,
+                    Some("@foo"),
+                    None,
+                    &Params::new()
+                )
                 .unwrap()
                 .history,
             Some(default_history)
         );
     }
+
+    #[test]
+    fn resolve_prompt_merges_params_by_key() {
+        use serde_json::json;
+
+        let params = |pairs: &[(&str, serde_json::Value)]| -> Params {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect()
+        };
+
+        let mut config = make_config_without_defaults();
+        config.default_model = Some("m".to_string());
+        config.default_params =
+            params(&[("temperature", json!(0.7)), ("seed", json!(1))]);
+        config.prompt[0].params =
+            params(&[("temperature", json!(0)), ("top_p", json!(0.9))]);
+
+        // Named prompt: CLI over prompt over default-params.
+        assert_eq!(
+            config
+                .resolve_prompt(
+                    None,
+                    None,
+                    Some("@foo"),
+                    None,
+                    &params(&[("seed", json!(42))])
+                )
+                .unwrap()
+                .params,
+            params(&[
+                ("seed", json!(42)),
+                ("temperature", json!(0)),
+                ("top_p", json!(0.9)),
+            ])
+        );
+
+        // Text question: CLI over default-params.
+        assert_eq!(
+            config
+                .resolve_prompt(
+                    None,
+                    None,
+                    Some("hi"),
+                    None,
+                    &params(&[("temperature", json!(1))])
+                )
+                .unwrap()
+                .params,
+            params(&[("seed", json!(1)), ("temperature", json!(1))])
+        );
+    }
+
+    #[test]
+    fn prompt_params_deserialize_from_toml() {
+        let prompt: Prompt = toml::from_str(
+            r#"
+            label = "summarize"
+            question = "Summarize."
+
+            [params]
+            temperature = 0
+            seed = 42
+            stop = ["\n\n"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(prompt.params["temperature"], 0);
+        assert_eq!(prompt.params["seed"], 42);
+        assert_eq!(prompt.params["stop"][0], "\n\n");
+    }
+
+    #[test]
+    fn check_params_rejects_reserved_keys() {
+        let config =
+            |toml: &str| toml::from_str::<Config>(toml).unwrap();
+
+        assert!(
+            config(
+                r#"
+                prompt = []
+
+                [default-params]
+                temperature = 0
+                "#
+            )
+            .check_params()
+            .is_ok()
+        );
+
+        assert_eq!(
+            config(
+                r#"
+                prompt = []
+
+                [default-params]
+                stream = true
+                "#
+            )
+            .check_params(),
+            Err("default-params: parameter \"stream\" is set by lui \
+                 itself"
+                .to_string())
+        );
+
+        assert_eq!(
+            config(
+                r#"
+                [[prompt]]
+                label = "summarize"
+                question = "Summarize."
+
+                [prompt.params]
+                model = "gemma"
+                "#
+            )
+            .check_params(),
+            Err("prompt \"summarize\": parameter \"model\" is set by \
+                 lui itself"
+                .to_string())
+        );
+    }
 }
@@ -1,7 +1,9 @@
 use base64::prelude::{BASE64_STANDARD, Engine as _};
 use glob::glob;
-use std::io::IsTerminal;
-use std::path::PathBuf;
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
+
+use crate::capture::{self, Scratch};
 
 pub type Label = String;
 pub type Content = String;
@@ -79,7 +81,7 @@ impl Context {
                         format!("data:{mime};base64,{data}"),
                     ));
                 }
-                Sniff::Document(kind) => {
+                Sniff::Document { name: kind, .. } => {
                     return Err(format!(
                         "{label}: looks like {kind}; send documents \
                          with -r/--rag, not -i"
@@ -104,23 +106,33 @@ impl Context {
     }
 
     /// Creates an empty context and loads each file that is matched by
-    /// a pattern in `include`.
+    /// a pattern in `include`.  Unless `stdin` is false, because stdin
+    /// is uploaded for RAG instead (`-r -`), stdin is loaded as well if
+    /// it is `-` in `include` or not a terminal.
     ///
     /// # Errors
     ///
     /// This method returns an error if
     ///
+    /// - `include` has `-` but `stdin` is false,
     /// - any of the specified glob patterns are invalid,
     /// - there was an error while traversing the filesystem to find
     ///   files that match the glob pattern, or
     /// - either stdin or the content of one of the matched files is not
     ///   valid UTF-8.
-    pub fn load(include: Option<&[String]>) -> Result<Self, String> {
+    pub fn load(
+        include: Option<&[String]>,
+        stdin: bool,
+    ) -> Result<Self, String> {
         let mut context = Self::new();
 
         if let Some(patterns) = include {
             for pattern in patterns {
-                if pattern == "-" {
+                if pattern == "-" && !stdin {
+                    return Err("stdin cannot be both context (-i -) \
+                                and a RAG file (-r -)"
+                        .to_string());
+                } else if pattern == "-" {
                     context.load_anonymous()?;
                 } else {
                     context.load_named(pattern)?;
@@ -128,7 +140,8 @@ impl Context {
             }
         }
 
-        if context.anonymous.is_none()
+        if stdin
+            && context.anonymous.is_none()
             && !std::io::stdin().is_terminal()
         {
             // The user didn't specify `--include -` but we are running
@@ -161,17 +174,62 @@ impl Context {
 
         result
     }
+
+    /// Lists what the context consists of: `stdin` for the anonymous
+    /// input, then the labels of the named files and the images.
+    pub fn manifest(&self) -> Vec<String> {
+        let stdin =
+            self.anonymous.as_ref().map(|_| "stdin".to_string());
+        let named = self.named.iter().map(|(label, _)| label.clone());
+        let images = self.images.iter().map(|(label, _)| label.clone());
+
+        stdin.into_iter().chain(named).chain(images).collect()
+    }
 }
 
-/// Expands the glob patterns in `patterns` into a flat list of file
-/// paths, deduplicated while preserving first-seen order.
+/// A file to upload for RAG.
+#[derive(Debug)]
+pub struct RagFile {
+    pub path: PathBuf,
+    /// The name to upload the file under: its path relative to the
+    /// directory given on the command line, with `/` between the
+    /// components, so that `docs/v1/api.md` and `docs/v2/api.md` can be
+    /// told apart.  See [`upload_name`].
+    pub name: String,
+    /// What the file is called in messages and in the citation footer:
+    /// its path, or for a [captured](crate::capture) source, the source
+    /// as given (e.g., `stdin`).
+    pub label: String,
+    /// Keeps the temporary copy of a captured source until the file has
+    /// been uploaded.
+    pub _scratch: Option<Scratch>,
+}
+
+impl RagFile {
+    fn on_disk(path: PathBuf, name: String) -> Self {
+        let label = path.to_string_lossy().into_owned();
+
+        Self {
+            path,
+            name,
+            label,
+            _scratch: None,
+        }
+    }
+}
+
+/// Expands the glob patterns in `patterns` into a flat list of files,
+/// deduplicated by path while preserving first-seen order.  A directory
+/// is walked recursively, skipping hidden files and the files that a
+/// `.gitignore` (or `.ignore`) in it or above it excludes.
 ///
 /// Unlike [`Context::load_named`], this does not read the matched files
 /// as UTF-8 text.  RAG files are uploaded to open-webui as raw bytes,
-/// so only their paths are needed here.  There is also no `-`/stdin
-/// case: a multipart upload needs a real file on disk.  Deduplication
-/// avoids uploading the same file twice when patterns overlap (e.g.
-/// `-r '*.txt' a.txt`).
+/// so only their paths are needed here.  Stdin (`-`), the output of a
+/// command (`cmd:<command>`), and URLs are [captured](crate::capture)
+/// in temporary files, since a multipart upload needs a real file on
+/// disk.  Deduplication avoids uploading the same file twice when
+/// patterns overlap (e.g. `-r '*.txt' a.txt`).
 ///
 /// # Errors
 ///
@@ -179,24 +237,112 @@ impl Context {
 ///
 /// - a glob pattern is invalid,
 /// - there was an error while traversing the filesystem to find files
-///   that match a glob pattern, or
-/// - a pattern matches no files.
+///   that match a glob pattern or that are in a directory, or
+/// - a pattern matches no files, or a directory has none, or
+/// - a source cannot be captured.
 pub fn expand_rag_paths(
     patterns: &[String],
-) -> Result<Vec<PathBuf>, String> {
-    let mut paths = Vec::new();
+) -> Result<Vec<RagFile>, String> {
+    let mut files = Vec::new();
     let mut seen = std::collections::HashSet::new();
 
+    let mut add = |file: RagFile| {
+        if seen.insert(file.path.clone()) {
+            files.push(file);
+        }
+    };
+
     for pattern in patterns {
+        if capture::is_capture(pattern) {
+            add(capture::capture(pattern)?);
+            continue;
+        }
+
         glob_each(pattern, |path| {
-            if seen.insert(path.clone()) {
-                paths.push(path);
+            if !path.is_dir() {
+                let name = upload_name(&path);
+                add(RagFile::on_disk(path, name));
+                return Ok(());
+            }
+
+            let found = walk_dir(&path)?;
+
+            if found.is_empty() {
+                return Err(format!(
+                    "{}: no files in the directory",
+                    path.to_string_lossy()
+                ));
+            }
+
+            for (path, name) in found {
+                add(RagFile::on_disk(path, name));
             }
+
             Ok(())
         })?;
     }
 
-    Ok(paths)
+    Ok(files)
+}
+
+/// Lists the files under the directory `dir`, honoring `.gitignore`
+/// even outside a Git repository, in a stable order, each with its
+/// [`upload_name`].
+fn walk_dir(dir: &Path) -> Result<Vec<(PathBuf, String)>, String> {
+    let prefix = upload_name(dir);
+    let mut files = Vec::new();
+
+    let walk = ignore::WalkBuilder::new(dir)
+        .require_git(false)
+        .sort_by_file_name(std::cmp::Ord::cmp)
+        .build();
+
+    for entry in walk {
+        let entry = entry
+            .map_err(|x| format!("{}: {x}", dir.to_string_lossy()))?;
+        let path = entry.path();
+
+        if !path.is_file() {
+            continue;
+        }
+
+        let relative =
+            upload_name(path.strip_prefix(dir).unwrap_or(path));
+        let name = if prefix.is_empty() {
+            relative
+        } else {
+            format!("{prefix}/{relative}")
+        };
+
+        files.push((path.to_path_buf(), name));
+    }
+
+    Ok(files)
+}
+
+/// Returns the name to upload the file or directory at `path` under:
+/// the path itself if it is relative, without any `.` and `..`
+/// components, and only its last component if it is absolute.  The
+/// components are joined with `/`.  A relative path without normal
+/// components, like `.`, yields an empty name.
+pub fn upload_name(path: &Path) -> String {
+    use std::path::Component;
+
+    let mut components: Vec<String> = path
+        .components()
+        .filter_map(|x| match x {
+            Component::Normal(x) => {
+                Some(x.to_string_lossy().into_owned())
+            }
+            _ => None,
+        })
+        .collect();
+
+    if path.is_absolute() && components.len() > 1 {
+        components.drain(..components.len() - 1);
+    }
+
+    components.join("/")
 }
 
 /// Expands the glob `pattern` and invokes `f` once per matched path.
@@ -229,13 +375,216 @@ fn glob_each(
     Ok(())
 }
 
-/// The classification of a file's bytes for `-i` handling.
+/// The MIME type that a file sent with `-r` is uploaded as.
+#[derive(Debug, PartialEq)]
+pub struct RagType {
+    pub mime: &'static str,
+    /// Why the type differs from what the extension suggests, if it
+    /// does.
+    pub mismatch: Option<String>,
+}
+
+/// How many leading bytes of a RAG file [`rag_type`] looks at.
+const SNIFF_LEN: u64 = 8192;
+
+/// Determines the MIME type to upload the RAG file at `path` as, by its
+/// content (see [`sniff`]) where that is recognizable and by its
+/// extension otherwise.  Errors name the file by `label`.
+///
+/// # Errors
+///
+/// This function returns an error if the file cannot be read, or if it
+/// is of a type that Open WebUI has no loader for: an image, a Zip or
+/// legacy Office file whose extension doesn't tell which format it
+/// holds, or a binary file of an unknown type.
+pub fn rag_type(path: &Path, label: &str) -> Result<RagType, String> {
+    let mut head = Vec::new();
+
+    std::fs::File::open(path)
+        .and_then(|x| x.take(SNIFF_LEN).read_to_end(&mut head))
+        .map_err(|x| format!("{label}: {x}"))?;
+
+    classify_rag(path, &head).map_err(|x| format!("{label}: {x}"))
+}
+
+fn classify_rag(path: &Path, head: &[u8]) -> Result<RagType, String> {
+    let by_extension = mime_for_extension(path);
+    let by_content = match sniff(head) {
+        Sniff::Image(_) => {
+            return Err("looks like an image; send images with -i, \
+                        not -r/--rag"
+                .to_string());
+        }
+        Sniff::Document { mime, .. } => Some(mime),
+        Sniff::Unknown if looks_like_text(head) => Some("text/plain"),
+        Sniff::Unknown => None,
+    };
+
+    let agree = |content: &str, extension: &str| {
+        content == extension
+            || match content {
+                "text/plain" => {
+                    extension.starts_with("text/")
+                        || extension == "application/json"
+                        || extension == "application/xml"
+                }
+                ZIP => ZIP_FORMATS.contains(&extension),
+                OLE => OLE_FORMATS.contains(&extension),
+                _ => false,
+            }
+    };
+
+    match (by_content, by_extension) {
+        (Some(content), Some(extension))
+            if agree(content, extension) =>
+        {
+            Ok(RagType {
+                mime: extension,
+                mismatch: None,
+            })
+        }
+        (Some(ZIP), _) => Err("looks like a Zip archive, which Open \
+                               WebUI has no loader for; if it is an \
+                               Office document or an e-book, give it \
+                               the right extension (docx, xlsx, pptx, \
+                               epub)"
+            .to_string()),
+        (Some(OLE), _) => Err("looks like a legacy Office document; \
+                               give it the right extension (doc, xls, \
+                               ppt)"
+            .to_string()),
+        (Some(content), None) => Ok(RagType {
+            mime: content,
+            mismatch: None,
+        }),
+        (Some(content), Some(extension)) => Ok(RagType {
+            mime: content,
+            mismatch: Some(format!(
+                "the extension suggests {extension}, but the content \
+                 looks like {content}; uploading it as {content}"
+            )),
+        }),
+        (None, Some(extension)) => Ok(RagType {
+            mime: extension,
+            mismatch: None,
+        }),
+        (None, None) => Err("neither text nor a known document \
+                             format, so Open WebUI has no loader \
+                             for it"
+            .to_string()),
+    }
+}
+
+/// Whether `head`, the leading bytes of a file, reads as UTF-8 text.
+/// A character cut off at the end of `head` doesn't count against it.
+fn looks_like_text(head: &[u8]) -> bool {
+    let valid = match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(x) => x.error_len().is_none(),
+    };
+
+    valid && !head.contains(&0)
+}
+
+/// The MIME type of a Zip container, which [`sniff`] can't tell the
+/// formats in [`ZIP_FORMATS`] apart from.
+const ZIP: &str = "application/zip";
+const ZIP_FORMATS: [&str; 4] = [
+    "application/epub+zip",
+    "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+];
+/// The MIME type of an OLE compound file, which [`sniff`] can't tell
+/// the formats in [`OLE_FORMATS`] apart from.
+const OLE: &str = "application/x-ole-storage";
+const OLE_FORMATS: [&str; 3] = [
+    "application/msword",
+    "application/vnd.ms-excel",
+    "application/vnd.ms-powerpoint",
+];
+
+/// Guesses a MIME type from the file extension so that open-webui can
+/// pick the right document loader.  Returns `None` for an unknown
+/// extension.
+fn mime_for_extension(path: &Path) -> Option<&'static str> {
+    let mime = match path
+        .extension()
+        .and_then(|x| x.to_str())
+        .map(str::to_ascii_lowercase)
+        .as_deref()
+    {
+        Some("csv") => "text/csv",
+        Some("doc") => "application/msword",
+        Some("docx") => {
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+        }
+        Some("epub") => "application/epub+zip",
+        Some("html") | Some("htm") => "text/html",
+        Some("json") => "application/json",
+        Some("md") | Some("markdown") => "text/markdown",
+        Some("pdf") => "application/pdf",
+        Some("ppt") => "application/vnd.ms-powerpoint",
+        Some("pptx") => {
+            "application/vnd.openxmlformats-officedocument.presentationml.presentation"
+        }
+        Some("rst") => "text/x-rst",
+        Some("tsv") => "text/tab-separated-values",
+        Some("txt") | Some("text") | Some("log") => "text/plain",
+        Some("xml") => "application/xml",
+        Some("xls") => "application/vnd.ms-excel",
+        Some("xlsx") => {
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+        }
+        _ => return None,
+    };
+
+    Some(mime)
+}
+
+/// Returns the extension that [`mime_for_extension`] maps to `mime`,
+/// for naming a file whose type is known but whose name is not.
+pub fn extension_for_mime(mime: &str) -> Option<&'static str> {
+    const EXTENSIONS: [&str; 16] = [
+        "txt", "md", "html", "csv", "tsv", "json", "xml", "rst", "pdf",
+        "doc", "docx", "ppt", "pptx", "xls", "xlsx", "epub",
+    ];
+
+    EXTENSIONS.into_iter().find(|x| {
+        mime_for_extension(Path::new(&format!("file.{x}")))
+            == Some(mime)
+    })
+}
+
+/// Returns the MIME type of the supported image format that `bytes`
+/// start with, if they start with one.
+pub fn image_mime(bytes: &[u8]) -> Option<&'static str> {
+    match sniff(bytes) {
+        Sniff::Image(mime) => Some(mime),
+        _ => None,
+    }
+}
+
+/// Returns the MIME type of the document format that `bytes` start
+/// with, if they start with one.
+pub fn sniff_mime(bytes: &[u8]) -> Option<&'static str> {
+    match sniff(bytes) {
+        Sniff::Document { mime, .. } => Some(mime),
+        _ => None,
+    }
+}
+
+/// The classification of a file's bytes, for `-i` and `-r` handling.
 enum Sniff {
     /// A supported image.  Carries its MIME type.
     Image(&'static str),
     /// A recognized document format.  Carries a human-readable name for
-    /// the "use -r/--rag" error message.
-    Document(&'static str),
+    /// the "use -r/--rag" error message, and its MIME type, which for a
+    /// Zip or OLE container is the container's.
+    Document {
+        name: &'static str,
+        mime: &'static str,
+    },
     /// Anything else.  Treated as (attempted) UTF-8 text.
     Unknown,
 }
@@ -263,23 +612,33 @@ fn sniff(bytes: &[u8]) -> Sniff {
 
     // Recognized document formats.  Return human-readable format name.
     if bytes.starts_with(b"%PDF-") {
-        return Sniff::Document("a PDF");
+        return Sniff::Document {
+            name: "a PDF",
+            mime: "application/pdf",
+        };
     }
     if bytes.starts_with(b"PK\x03\x04")
         || bytes.starts_with(b"PK\x05\x06")
         || bytes.starts_with(b"PK\x07\x08")
     {
-        return Sniff::Document(
-            "a Zip/Office document (docx, xlsx, pptx, epub)",
-        );
+        return Sniff::Document {
+            name: "a Zip/Office document (docx, xlsx, pptx, epub)",
+            mime: ZIP,
+        };
     }
     if bytes
         .starts_with(&[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1])
     {
-        return Sniff::Document("a legacy Office document");
+        return Sniff::Document {
+            name: "a legacy Office document",
+            mime: OLE,
+        };
     }
     if bytes.starts_with(b"{\\rtf") {
-        return Sniff::Document("an RTF document");
+        return Sniff::Document {
+            name: "an RTF document",
+            mime: "application/rtf",
+        };
     }
 
     Sniff::Unknown
@@ -295,7 +654,7 @@ mod tests {
             expand_rag_paths(&["src/*.rs".to_string()]).unwrap();
 
         assert!(
-            paths.iter().any(|p| p.ends_with("context.rs")),
+            paths.iter().any(|p| p.path.ends_with("context.rs")),
             "expected context.rs among {paths:?}"
         );
     }
@@ -321,12 +680,140 @@ mod tests {
         ])
         .unwrap();
 
-        let count =
-            paths.iter().filter(|p| p.ends_with("context.rs")).count();
+        let count = paths
+            .iter()
+            .filter(|p| p.path.ends_with("context.rs"))
+            .count();
 
         assert_eq!(count, 1, "context.rs duplicated in {paths:?}");
     }
 
+    #[test]
+    fn expand_rag_paths_walks_directories_honoring_gitignore() {
+        let root = std::env::temp_dir()
+            .join(format!("lui-rag-walk-{}", std::process::id()));
+        let docs = root.join("docs");
+
+        for (path, content) in [
+            ("v1/api.md", "one"),
+            ("v2/api.md", "two"),
+            ("v2/build/out.md", "ignored"),
+            (".hidden.md", "hidden"),
+            (".gitignore", "build/\n"),
+        ] {
+            let path = docs.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        let files =
+            expand_rag_paths(&[docs.to_string_lossy().into_owned()])
+                .unwrap();
+        let names: Vec<&str> =
+            files.iter().map(|x| x.name.as_str()).collect();
+
+        assert_eq!(names, ["docs/v1/api.md", "docs/v2/api.md"]);
+        assert_eq!(files[1].path, docs.join("v2/api.md"));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn upload_name_keeps_relative_paths_only() {
+        assert_eq!(
+            upload_name(Path::new("../docs/v1/api.md")),
+            "docs/v1/api.md"
+        );
+        assert_eq!(
+            upload_name(Path::new("/tmp/report.pdf")),
+            "report.pdf"
+        );
+        assert_eq!(upload_name(Path::new(".")), "");
+    }
+
+    #[test]
+    fn mime_for_extension_maps_common_extensions() {
+        assert_eq!(
+            mime_for_extension(Path::new("a.pdf")),
+            Some("application/pdf")
+        );
+        // Case-insensitive on the extension.
+        assert_eq!(
+            mime_for_extension(Path::new("A.PDF")),
+            Some("application/pdf")
+        );
+        assert_eq!(
+            mime_for_extension(Path::new("sheet.xlsx")),
+            Some(
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+            )
+        );
+        assert_eq!(
+            mime_for_extension(Path::new("deck.pptx")),
+            Some(
+                "application/vnd.openxmlformats-officedocument.presentationml.presentation"
+            )
+        );
+        assert_eq!(
+            mime_for_extension(Path::new("book.epub")),
+            Some("application/epub+zip")
+        );
+        // An extensionless filename has no type.
+        assert_eq!(mime_for_extension(Path::new("notes")), None);
+        assert_eq!(
+            mime_for_extension(Path::new("archive.tar.gz")),
+            None
+        );
+    }
+
+    #[test]
+    fn classify_rag_trusts_the_content_over_the_extension() {
+        let mime = |name: &str, head: &[u8]| {
+            classify_rag(Path::new(name), head).map(|x| x.mime)
+        };
+
+        assert_eq!(mime("a.pdf", b"%PDF-1.7"), Ok("application/pdf"));
+        assert_eq!(mime("notes.md", b"# Notes"), Ok("text/markdown"));
+        assert_eq!(mime("README", b"Read me."), Ok("text/plain"));
+        assert_eq!(
+            mime("report.docx", b"PK\x03\x04"),
+            Ok(
+                "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+            )
+        );
+
+        assert_eq!(
+            mime(
+                "old.xls",
+                &[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1]
+            ),
+            Ok("application/vnd.ms-excel")
+        );
+
+        // A PDF with the wrong extension goes up as a PDF, with a
+        // warning.
+        let pdf =
+            classify_rag(Path::new("a.txt"), b"%PDF-1.4").unwrap();
+        assert_eq!(pdf.mime, "application/pdf");
+        assert!(pdf.mismatch.unwrap().contains("text/plain"));
+
+        // A binary file of an unknown type is trusted by its extension
+        // only.
+        assert_eq!(mime("a.json", &[0, 1, 2]), Ok("application/json"));
+
+        assert!(mime("a.png", b"\x89PNG\r\n\x1a\nrest").is_err());
+        assert!(mime("files.zip", b"PK\x03\x04").is_err());
+        assert!(mime("blob", &[0, 1, 2]).is_err());
+    }
+
+    #[test]
+    fn looks_like_text_allows_a_character_cut_off_at_the_end() {
+        assert!(looks_like_text("naïve".as_bytes()));
+        assert!(looks_like_text(&"naïve".as_bytes()[..3]));
+        assert!(!looks_like_text(b"a\0b"));
+        assert!(!looks_like_text(&[0xFF, 0xFE, b'a']));
+    }
+
     #[test]
     fn sniff_classifies_by_magic_bytes() {
         assert!(matches!(
@@ -350,15 +837,18 @@ mod tests {
         // GIF87a as well as GIF89a.
         assert!(matches!(sniff(b"GIF87a;"), Sniff::Image("image/gif")));
 
-        assert!(matches!(sniff(b"%PDF-1.7"), Sniff::Document(_)));
-        assert!(matches!(sniff(b"PK\x03\x04zip"), Sniff::Document(_)));
-        assert!(matches!(sniff(b"{\\rtf1"), Sniff::Document(_)));
+        assert!(matches!(sniff(b"%PDF-1.7"), Sniff::Document { .. }));
+        assert!(matches!(
+            sniff(b"PK\x03\x04zip"),
+            Sniff::Document { .. }
+        ));
+        assert!(matches!(sniff(b"{\\rtf1"), Sniff::Document { .. }));
         // Legacy OLE compound document (.doc/.xls/.ppt).
         assert!(matches!(
             sniff(&[
                 0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1, 0x00
             ]),
-            Sniff::Document(_)
+            Sniff::Document { .. }
         ));
 
         assert!(matches!(sniff(b"plain text"), Sniff::Unknown));
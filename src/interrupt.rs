@@ -0,0 +1,99 @@
+//! Ctrl-C (SIGINT) handling while lui waits for the model.
+//!
+//! The first Ctrl-C only sets a flag.  The response loop checks it,
+//! stops reading, and drops the response, which closes the connection.
+//! Open WebUI serves a completion request from the API inline, without
+//! a background task to stop (it only creates one for the requests of
+//! its web UI, which carry a socket session), so there is no task ID
+//! for its task-stop endpoint, and closing the connection is what makes
+//! it stop generating.  Lui then deletes the RAG files it uploaded for
+//! the query and exits with [`EXIT_STATUS`].
+//!
+//! While lui is still [waiting](set_waiting) for the first token, e.g.,
+//! because the model is processing the prompt, there is no token to
+//! trigger the check, so Ctrl-C quits right away, as does a second
+//! Ctrl-C at any time.  Before quitting, it runs the action set with
+//! [`on_quit`], which deletes the uploads.  In `--chat` mode, this ends
+//! the session.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The conventional exit status after SIGINT (128 + 2).
+pub const EXIT_STATUS: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+static WAITING: AtomicBool = AtomicBool::new(false);
+
+type Action = Box<dyn FnOnce() + Send>;
+
+static ON_QUIT: Mutex<Option<Action>> = Mutex::new(None);
+
+/// Installs the Ctrl-C handler.
+///
+/// # Errors
+///
+/// This function returns an error if the handler cannot be installed,
+/// including when it already has been.
+pub fn install() -> Result<(), String> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst)
+            || WAITING.load(Ordering::SeqCst)
+        {
+            eprintln!();
+            quit();
+        }
+
+        // Start on a new line in case a response is being printed.
+        eprintln!();
+        log::warn!("interrupted (press Ctrl-C again to quit now)");
+    })
+    .map_err(|x| format!("cannot install Ctrl-C handler: {x}"))
+}
+
+fn quit() -> ! {
+    let action = ON_QUIT.lock().ok().and_then(|mut x| x.take());
+
+    if let Some(action) = action {
+        log::warn!("quitting; cleaning up first");
+        action();
+    }
+
+    std::process::exit(EXIT_STATUS);
+}
+
+/// Sets what to do before Ctrl-C quits lui, replacing what was set
+/// before.
+pub fn on_quit(action: impl FnOnce() + Send + 'static) {
+    if let Ok(mut x) = ON_QUIT.lock() {
+        *x = Some(Box::new(action));
+    }
+}
+
+/// Clears the action set with [`on_quit`], once lui has done it itself.
+pub fn clear_on_quit() {
+    if let Ok(mut x) = ON_QUIT.lock() {
+        *x = None;
+    }
+}
+
+/// Tells whether lui is waiting for the first token of a response, so
+/// that Ctrl-C quits right away rather than wait for a token that may
+/// take long to come.
+pub fn set_waiting(waiting: bool) {
+    WAITING.store(waiting, Ordering::SeqCst);
+}
+
+/// Whether Ctrl-C has been pressed since [`install`] or the last
+/// [`reset`].
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Clears the flag after an interruption has been dealt with without
+/// exiting (in `--chat` mode), so that the next Ctrl-C is again only a
+/// request to stop.
+pub fn reset() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
@@ -1,25 +1,37 @@
+//! Tracks the IDs of files that lui has uploaded to open-webui but not
+//! yet deleted, so that `--prune` can clean up uploads left behind by a
+//! crash or a failed cleanup.
+//!
+//! Each uploaded ID gets its own marker file (`<pending_dir>/<id>`).
+//! Using one file per ID means concurrent `lui` invocations never
+//! clobber each other's records, so no ID is ever lost to a
+//! read-modify-write race.
+
 use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime};
 
-/// Tracks the IDs of files that lui has uploaded to open-webui but not
-/// yet deleted, so that `--prune` can clean up uploads left behind by a
-/// crash or a failed cleanup.
+/// Returns the directory in which upload markers are stored for the
+/// server profile `server`: `$HOME/.local/state/lui/pending` for the
+/// unnamed `[server]` table, and
+/// `$HOME/.local/state/lui/servers/<server>/pending` for a named one.
 ///
-/// Each uploaded ID gets its own marker file (`<pending_dir>/<id>`).
-/// Using one file per ID means concurrent `lui` invocations never
-/// clobber each other's records, so no ID is ever lost to a
-/// read-modify-write race.
-
-/// Returns the directory in which upload markers are stored
-/// (`$HOME/.local/state/lui/pending`).
+/// Keeping the markers apart per profile means that `--prune` against
+/// one server never drops the records of uploads to another (which it
+/// would, because a delete that returns `404` counts as success).
 ///
 /// Returns `None` if the user's home directory cannot be determined.
-pub fn pending_dir() -> Option<PathBuf> {
+pub fn pending_dir(server: Option<&str>) -> Option<PathBuf> {
     let mut path = std::env::home_dir()?;
 
     path.push(".local");
     path.push("state");
     path.push("lui");
+
+    if let Some(name) = server {
+        path.push("servers");
+        path.push(name);
+    }
+
     path.push("pending");
 
     Some(path)
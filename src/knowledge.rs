@@ -0,0 +1,241 @@
+//! Open WebUI's knowledge collections: curated sets of files that are
+//! indexed once and can be referenced by any number of requests
+//! (`--knowledge`), unlike the files that `-r` uploads for one query.
+//!
+//! Files are added to a collection by uploading them to the file store
+//! first, as `-r` does, and then adding the uploaded file to the
+//! collection, which indexes it again under the collection.
+
+use serde_json::{Value, json};
+
+use crate::server::{Server, is_safe_id};
+
+/// A knowledge collection, as `--knowledge` refers to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Collection {
+    pub id: String,
+    pub name: String,
+}
+
+/// Lists the collections the user can read, as the raw entries (`id`,
+/// `name`, `description`, and so on).
+///
+/// # Errors
+///
+/// This function returns an error if the request fails or the response
+/// is not a list.
+pub fn list(server: &Server) -> Result<Vec<Value>, String> {
+    let value: Value = server
+        .agent()?
+        .get(&server.url("/api/v1/knowledge/")?)
+        .header("Authorization", &server.bearer()?)
+        .call()
+        .map_err(|x| format!("{x}"))?
+        .into_body()
+        .read_json()
+        .map_err(|x| format!("{x}"))?;
+
+    // Newer versions of Open WebUI return a page of `items`.
+    value
+        .as_array()
+        .or_else(|| value["items"].as_array())
+        .cloned()
+        .ok_or_else(|| "malformed knowledge list".to_string())
+}
+
+/// Finds the collection whose ID or name is `name_or_id`.  An ID takes
+/// precedence over a name.
+///
+/// # Errors
+///
+/// This function returns an error if listing the collections fails, or
+/// if no collection or more than one collection matches.
+pub fn find(
+    server: &Server,
+    name_or_id: &str,
+) -> Result<Collection, String> {
+    let collections = list(server)?;
+
+    find_in(&collections, name_or_id)
+}
+
+fn find_in(
+    collections: &[Value],
+    name_or_id: &str,
+) -> Result<Collection, String> {
+    let to_collection = |x: &Value| -> Option<Collection> {
+        Some(Collection {
+            id: x["id"].as_str().filter(|x| is_safe_id(x))?.to_string(),
+            name: x["name"].as_str().unwrap_or_default().to_string(),
+        })
+    };
+
+    let all: Vec<Collection> =
+        collections.iter().filter_map(to_collection).collect();
+
+    if let Some(collection) = all.iter().find(|x| x.id == name_or_id) {
+        return Ok(collection.clone());
+    }
+
+    let mut named = all.into_iter().filter(|x| x.name == name_or_id);
+
+    match (named.next(), named.next()) {
+        (Some(collection), None) => Ok(collection),
+        (Some(_), Some(_)) => Err(format!(
+            "more than one knowledge collection is named '{name_or_id}'; \
+             use its ID instead"
+        )),
+        (None, _) => Err(format!(
+            "knowledge collection '{name_or_id}' not found"
+        )),
+    }
+}
+
+/// Creates a collection called `name`.
+///
+/// # Errors
+///
+/// This function returns an error if the request fails or the response
+/// has no ID.
+pub fn create(
+    server: &Server,
+    name: &str,
+) -> Result<Collection, String> {
+    let value: Value = server
+        .agent()?
+        .post(&server.url("/api/v1/knowledge/create")?)
+        .header("Authorization", &server.bearer()?)
+        .send_json(json!({ "name": name, "description": "" }))
+        .map_err(|x| format!("{x}"))?
+        .into_body()
+        .read_json()
+        .map_err(|x| format!("{x}"))?;
+
+    let id = value["id"]
+        .as_str()
+        .filter(|x| is_safe_id(x))
+        .ok_or_else(|| {
+            "malformed response to a new knowledge collection"
+                .to_string()
+        })?;
+
+    Ok(Collection {
+        id: id.to_string(),
+        name: name.to_string(),
+    })
+}
+
+/// Lists the files in `collection`, as `(id, name)` pairs.
+///
+/// # Errors
+///
+/// This function returns an error if the request fails or the response
+/// has no list of files.
+pub fn files(
+    server: &Server,
+    collection: &Collection,
+) -> Result<Vec<(String, String)>, String> {
+    let id = &collection.id;
+
+    let value: Value = server
+        .agent()?
+        .get(&server.url(&format!("/api/v1/knowledge/{id}"))?)
+        .header("Authorization", &server.bearer()?)
+        .call()
+        .map_err(|x| format!("{}: {x}", collection.name))?
+        .into_body()
+        .read_json()
+        .map_err(|x| format!("{}: {x}", collection.name))?;
+
+    let files = value["files"].as_array().ok_or_else(|| {
+        format!("{}: malformed knowledge collection", collection.name)
+    })?;
+
+    Ok(files
+        .iter()
+        .filter_map(|file| {
+            let id = file["id"].as_str()?.to_string();
+            let name = file["meta"]["name"]
+                .as_str()
+                .or_else(|| file["filename"].as_str())
+                .unwrap_or_default()
+                .to_string();
+
+            Some((id, name))
+        })
+        .collect())
+}
+
+/// Adds the uploaded file `file_id` to `collection`.  The request
+/// blocks until the file has been indexed for the collection.
+///
+/// # Errors
+///
+/// This function returns an error if the request fails, e.g., because
+/// the file could not be indexed.
+pub fn add_file(
+    server: &Server,
+    collection: &Collection,
+    file_id: &str,
+) -> Result<(), String> {
+    change_file(server, collection, "add", file_id)
+}
+
+/// Removes the file `file_id` from `collection`, which also deletes it
+/// from the file store.
+///
+/// # Errors
+///
+/// This function returns an error if the request fails.
+pub fn remove_file(
+    server: &Server,
+    collection: &Collection,
+    file_id: &str,
+) -> Result<(), String> {
+    change_file(server, collection, "remove", file_id)
+}
+
+fn change_file(
+    server: &Server,
+    collection: &Collection,
+    action: &str,
+    file_id: &str,
+) -> Result<(), String> {
+    let id = &collection.id;
+
+    server
+        .agent()?
+        .post(
+            &server.url(&format!(
+                "/api/v1/knowledge/{id}/file/{action}"
+            ))?,
+        )
+        .header("Authorization", &server.bearer()?)
+        .send_json(json!({ "file_id": file_id }))
+        .map_err(|x| format!("{}: {x}", collection.name))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_in_prefers_ids_and_rejects_ambiguous_names() {
+        let collections = vec![
+            json!({ "id": "kb-1", "name": "Handbook" }),
+            json!({ "id": "kb-2", "name": "Notes" }),
+            json!({ "id": "kb-3", "name": "Notes" }),
+            json!({ "id": "kb-4", "name": "kb-1" }),
+        ];
+
+        let find = |x| find_in(&collections, x);
+
+        assert_eq!(find("Handbook").unwrap().id, "kb-1");
+        assert_eq!(find("kb-1").unwrap().name, "Handbook");
+        assert_eq!(find("kb-3").unwrap().name, "Notes");
+        assert!(find("Notes").unwrap_err().contains("more than one"));
+        assert!(find("Manual").unwrap_err().contains("not found"));
+    }
+}
@@ -14,7 +14,12 @@ impl Logger {
 
 impl log::Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
+        // ureq dumps raw request bytes, including the `Authorization`
+        // header with the API key, at the trace level.  Only lui's own
+        // trace records are let through.
         metadata.level() <= self.max_level
+            && (metadata.level() < Level::Trace
+                || metadata.target().starts_with("lui"))
     }
 
     fn log(&self, record: &Record) {
@@ -42,3 +47,21 @@ pub fn init(max_level: Level) -> Result<(), SetLoggerError> {
     log::set_logger(logger)
         .map(|()| log::set_max_level(LevelFilter::Trace))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Log;
+
+    #[test]
+    fn trace_records_from_dependencies_are_dropped() {
+        let logger = Logger::new(Level::Trace);
+        let metadata = |level, target| {
+            Metadata::builder().level(level).target(target).build()
+        };
+
+        assert!(logger.enabled(&metadata(Level::Trace, "lui::server")));
+        assert!(logger.enabled(&metadata(Level::Debug, "ureq::pool")));
+        assert!(!logger.enabled(&metadata(Level::Trace, "ureq_proto")));
+    }
+}
@@ -1,21 +1,42 @@
 use clap::{ArgAction, ArgGroup, Parser};
 use std::borrow::Cow;
 use std::io::Write;
-use std::path::Path;
-
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+mod backend;
+mod capture;
+mod chat;
+mod chats;
 mod config;
 mod context;
+mod interrupt;
 mod journal;
+mod knowledge;
 mod logger;
+mod ollama;
+mod openai;
 mod prompt;
+mod registry;
+mod schema;
+mod secret;
 mod server;
+mod session;
+mod tokens;
 
+use crate::backend::{Backend, BackendKind, Indexing};
+use crate::chat::{Chat, Command};
+use crate::chats::ChatMessage;
 use crate::config::Config;
 use crate::context::Context;
+use crate::knowledge::Collection;
+use crate::schema::Schema;
 use crate::server::{
-    Message, Output, OutputReader, Server, parse_message,
+    FileRef, Message, Output, OutputReader, Server, parse_message,
     remove_think_block,
 };
+use crate::session::{Exchange, Session};
 
 /// Command-line interface to open-webui.
 #[derive(Debug, Parser)]
@@ -23,14 +44,45 @@ use crate::server::{
 #[command(
     group = ArgGroup::new("prune_mode")
         .args(["prune", "prune_all"])
+        .multiple(true),
+    group = ArgGroup::new("query")
+        .args([
+            "question", "rag", "knowledge", "include", "model",
+            "history", "system", "params", "keep_think_block",
+            "no_stream", "hide_excerpts", "no_context_check", "schema",
+            "schema_retries", "chat", "continue_session", "session",
+            "no_session", "save_chat", "from_chat", "keep_uploads",
+            "reuse_uploads", "reuse_as", "upload_jobs",
+            "index_in_background", "index_timeout", "rag_full",
+        ])
+        .multiple(true),
+    group = ArgGroup::new("operation")
+        .args([
+            "prune", "prune_all", "list_models", "show_model",
+            "list_prompts", "list_sessions", "show_session",
+            "rm_session", "list_chats", "list_knowledge",
+            "create_knowledge", "add_to_knowledge",
+            "remove_from_knowledge",
+        ])
+        .conflicts_with("query"),
+    group = ArgGroup::new("uploading")
+        .args(["rag", "chat", "add_to_knowledge"])
         .multiple(true)
 )]
 struct Args {
     /// Files to feed to open-webui's RAG API for use with the prompt.
-    /// (Can be glob patterns.)
+    /// (Can be glob patterns, directories, which are searched
+    /// recursively, honoring .gitignore, '-' for stdin, 'cmd:COMMAND'
+    /// for the output of a shell command, an http(s) URL, or '@NAME'
+    /// for an upload named with --reuse-as.)
     #[arg(long, short, num_args = 1..)]
     rag: Option<Vec<String>>,
 
+    /// Retrieve from this Open WebUI knowledge collection, given by name
+    /// or ID, along with any -r files. May be repeated.
+    #[arg(long, value_name = "NAME-OR-ID")]
+    knowledge: Vec<String>,
+
     /// Files to include in the prompt sent to the model. (Can be glob
     /// patterns, or '-' for stdin.) Image files (PNG/JPEG/GIF/WebP) are
     /// detected by content and sent to vision-capable models.
@@ -56,8 +108,21 @@ struct Args {
     #[arg(long, short)]
     system: Option<String>,
 
-    /// Print the model's response in JSON form.
-    #[arg(long, short = 'j')]
+    /// Set a generation parameter (e.g., temperature=0, seed=42,
+    /// stop='["\n\n"]'), overriding the prompt's [prompt.params] and
+    /// default-params. The value is parsed as JSON if possible and
+    /// taken as a string otherwise. May be repeated.
+    #[arg(
+        long = "param",
+        value_name = "KEY=VALUE",
+        value_parser = prompt::parse_param,
+    )]
+    params: Vec<(String, serde_json::Value)>,
+
+    /// Print the model's response, or the output of --list-models,
+    /// --show-model, --list-prompts, --list-sessions, --show-session,
+    /// --list-chats, and --list-knowledge, in JSON form.
+    #[arg(long, short = 'j', visible_alias = "json")]
     output_json: bool,
 
     /// Keep the <think></think> block if the model's response has it.
@@ -72,36 +137,134 @@ struct Args {
     #[arg(long, short = 'E')]
     hide_excerpts: bool,
 
+    /// Send the request even if it looks too large for the model's
+    /// context window (num_ctx), and skip looking up the window.
+    #[arg(long)]
+    no_context_check: bool,
+
+    /// Ask for a JSON answer that matches the JSON schema in this file,
+    /// and validate the answer. Only the validated JSON is printed. If
+    /// no answer matches, lui exits with an error.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["output_json", "keep_think_block"]
+    )]
+    schema: Option<PathBuf>,
+
+    /// With --schema, how many times to ask the model again, with the
+    /// validation errors, after an answer that doesn't match.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 2,
+        requires = "schema"
+    )]
+    schema_retries: u32,
+
+    /// Start an interactive chat. The context and the RAG uploads are
+    /// kept for the whole session, and each question is sent with the
+    /// earlier questions and answers. Type /help for the commands. The
+    /// question, if given, is asked first.
+    #[arg(long, conflicts_with_all = ["schema", "output_json"])]
+    chat: bool,
+
+    /// Continue the most recent session: send its questions and answers
+    /// as history, and add this exchange to it. Without --continue or
+    /// --session, each run starts a new session, unless --no-session is
+    /// given.
+    #[arg(long = "continue", short = 'c')]
+    continue_session: bool,
+
+    /// Continue the session with this name, or start it if there is no
+    /// such session.
+    #[arg(
+        long,
+        value_name = "NAME",
+        conflicts_with = "continue_session"
+    )]
+    session: Option<String>,
+
+    /// Don't save the questions and answers of this run in a session.
+    #[arg(long, conflicts_with_all = ["continue_session", "session"])]
+    no_session: bool,
+
+    /// Save the exchange to Open WebUI's chat history, so that it shows
+    /// up in the web UI, and print the chat's URL. Without an ID, a new
+    /// chat is created. With the ID of an existing chat, the exchange is
+    /// added to the end of it. (Put the question before this option, or
+    /// after '--'.)
+    #[arg(long, value_name = "ID", num_args = 0..=1)]
+    save_chat: Option<Option<String>>,
+
+    /// Use the Open WebUI chat with this ID as the conversation history,
+    /// e.g., to continue a chat started in the web UI with local files
+    /// as context. See --list-chats for the IDs.
+    #[arg(long, value_name = "ID")]
+    from_chat: Option<String>,
+
     /// Don't delete files uploaded for RAG (-r) after the query.
     #[arg(long)]
     keep_uploads: bool,
 
+    /// Keep the files uploaded for RAG (-r) after the query, and reuse
+    /// them instead of uploading a file with the same content again in
+    /// a later run.
+    #[arg(long)]
+    reuse_uploads: bool,
+
+    /// Like --reuse-uploads, and name the upload so that '-r @NAME'
+    /// refers to it in later runs. The RAG patterns must match exactly
+    /// one file.
+    #[arg(long, value_name = "NAME", requires = "rag")]
+    reuse_as: Option<String>,
+
+    /// How many RAG files to upload at the same time. Open WebUI
+    /// indexes each file before it answers the upload, so uploading
+    /// several files in parallel can save a lot of time.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 4,
+        value_parser = clap::value_parser!(u16).range(1..=32)
+    )]
+    upload_jobs: u16,
+
+    /// Let Open WebUI index the RAG files after the upload, and wait
+    /// for it while showing the progress, instead of waiting for the
+    /// upload itself to finish. Try this if uploads of large documents
+    /// time out.
+    #[arg(long)]
+    index_in_background: bool,
+
+    /// With background indexing, how many seconds to wait for all RAG
+    /// files to be indexed. [default: 600]
+    #[arg(long, value_name = "SECS")]
+    index_timeout: Option<u64>,
+
+    /// Refuse to upload a RAG file, or a file for --add-to-knowledge,
+    /// larger than this many megabytes.
+    #[arg(long, value_name = "MB", requires = "uploading")]
+    max_upload_size: Option<u64>,
+
+    /// Have Open WebUI give the model the whole text of each RAG file
+    /// instead of the passages that retrieval picks. Try this if the
+    /// answer misses what a file says.
+    #[arg(long, requires = "rag")]
+    rag_full: bool,
+
     /// Delete RAG files this machine uploaded but never cleaned up
     /// (e.g., after a crash), then exit. This is a standalone
     /// maintenance operation and cannot be combined with a prompt or
     /// any prompting option.
-    #[arg(
-        long,
-        conflicts_with_all = [
-            "prune_all", "question", "rag", "include", "history",
-            "model", "system", "output_json", "keep_think_block",
-            "no_stream", "keep_uploads", "hide_excerpts",
-        ]
-    )]
+    #[arg(long, conflicts_with = "output_json")]
     prune: bool,
 
     /// Delete EVERY file the user can access on the server, including
     /// persistent files and ones not uploaded by lui, then exit.
     /// Requires --yes. Like --prune, this is a standalone maintenance
     /// operation and cannot be combined with a prompt.
-    #[arg(
-        long,
-        conflicts_with_all = [
-            "question", "rag", "include", "history", "model",
-            "system", "output_json", "keep_think_block", "no_stream",
-            "keep_uploads", "hide_excerpts",
-        ]
-    )]
+    #[arg(long, conflicts_with = "output_json")]
     prune_all: bool,
 
     /// Confirm the destructive --prune-all operation.
@@ -113,6 +276,77 @@ struct Args {
     #[arg(long, requires = "prune_mode")]
     dry_run_prune: bool,
 
+    /// List the models available on the server with their owners,
+    /// context lengths, and capabilities, then exit.
+    #[arg(long)]
+    list_models: bool,
+
+    /// Show the details of the model with this ID, including the
+    /// parameters configured for it in Open WebUI (e.g., num_ctx), then
+    /// exit.
+    #[arg(long, value_name = "ID")]
+    show_model: Option<String>,
+
+    /// List the prompts in the configuration with their effective
+    /// model and system prompt, marking the default prompt, then exit.
+    #[arg(long)]
+    list_prompts: bool,
+
+    /// List the saved sessions, most recent first, with their number of
+    /// turns and first question, then exit.
+    #[arg(long)]
+    list_sessions: bool,
+
+    /// Print the session with this name as Markdown, or with --json as
+    /// it is stored, then exit.
+    #[arg(long, value_name = "NAME")]
+    show_session: Option<String>,
+
+    /// Delete the session with this name, then exit.
+    #[arg(long, value_name = "NAME", conflicts_with = "output_json")]
+    rm_session: Option<String>,
+
+    /// List the chats in Open WebUI's chat history, most recently
+    /// updated first, with their IDs and titles, then exit.
+    #[arg(long)]
+    list_chats: bool,
+
+    /// List the knowledge collections with their IDs and descriptions,
+    /// then exit.
+    #[arg(long)]
+    list_knowledge: bool,
+
+    /// Create a knowledge collection with this name, print its ID, then
+    /// exit.
+    #[arg(long, value_name = "NAME", conflicts_with = "output_json")]
+    create_knowledge: Option<String>,
+
+    /// Upload the files (can be glob patterns) and add them to the
+    /// knowledge collection, given by name or ID, then exit.
+    #[arg(
+        long,
+        num_args = 2..,
+        value_names = ["COLLECTION", "FILE"],
+        conflicts_with = "output_json"
+    )]
+    add_to_knowledge: Option<Vec<String>>,
+
+    /// Remove the files, given by name or ID, from the knowledge
+    /// collection, given by name or ID, which also deletes them from
+    /// the server, then exit.
+    #[arg(
+        long,
+        num_args = 2..,
+        value_names = ["COLLECTION", "FILE"],
+        conflicts_with = "output_json"
+    )]
+    remove_from_knowledge: Option<Vec<String>>,
+
+    /// Use this server profile from [servers.<name>] in the
+    /// configuration instead of the default one.
+    #[arg(long, short = 'P')]
+    server: Option<String>,
+
     /// Set log level (-v for info, -vv for debug, -vvv for trace).
     #[arg(long, short, action = ArgAction::Count)]
     verbose: u8,
@@ -258,37 +492,126 @@ where
 fn process(args: &Args) -> Result<(), String> {
     let config = Config::load()?;
 
-    // Prune subcommands don't need a prompt or context, and would
-    // otherwise fail in resolve_prompt when no question is given.
+    if args.list_prompts {
+        return list_prompts(&config, args.output_json);
+    }
+
+    if args.list_sessions {
+        return list_sessions(args.output_json);
+    }
+
+    if let Some(ref name) = args.show_session {
+        return show_session(name, args.output_json);
+    }
+
+    if let Some(ref name) = args.rm_session {
+        return session::remove(&sessions_dir()?, name);
+    }
+
+    let server = config.resolve_server(args.server.as_deref())?;
+
+    // Listing and prune subcommands don't need a prompt or context,
+    // and would otherwise fail in resolve_prompt when no question is
+    // given.
+    if args.list_models {
+        return list_models(server, args.output_json);
+    }
+
+    if let Some(ref id) = args.show_model {
+        return show_model(server, id, args.output_json);
+    }
+
+    if args.list_chats {
+        return list_chats(server, args.output_json);
+    }
+
+    if args.list_knowledge {
+        return list_knowledge(server, args.output_json);
+    }
+
+    if let Some(ref name) = args.create_knowledge {
+        return create_knowledge(server, name);
+    }
+
+    if let Some([collection, patterns @ ..]) =
+        args.add_to_knowledge.as_deref()
+    {
+        return add_to_knowledge(
+            server,
+            collection,
+            patterns,
+            max_upload_size(args, server),
+        );
+    }
+
+    if let Some([collection, files @ ..]) =
+        args.remove_from_knowledge.as_deref()
+    {
+        return remove_from_knowledge(server, collection, files);
+    }
+
     if args.prune {
-        return prune(&config.server, args.dry_run_prune);
+        return prune(server, args.dry_run_prune);
     }
 
     if args.prune_all {
-        return prune_all(&config.server, args.yes, args.dry_run_prune);
+        return prune_all(server, args.yes, args.dry_run_prune);
     }
 
-    warn_if_stale_uploads();
+    warn_if_stale_uploads(server);
+
+    if args.chat {
+        return chat(args, &config, server);
+    }
 
-    let prompt = config.resolve_prompt(
+    let mut prompt = config.resolve_prompt(
         args.history.as_deref(),
         args.system.as_deref(),
         args.question.as_deref(),
         args.model.as_deref(),
+        &args.params.iter().cloned().collect(),
     )?;
 
-    let context = Context::load(args.include.as_deref())?;
+    let schema =
+        args.schema.as_deref().map(Schema::load).transpose()?;
+
+    prompt.schema = schema.as_ref().map(|x| x.value().clone());
+
+    if let Some(ref id) = args.from_chat {
+        import_chat(server, &mut prompt, id)?;
+    }
+
+    let mut session = open_session(args)?;
+    let prompt = session.continue_prompt(&prompt);
+    let mut web_chat = chat_target(args, server)?;
+
+    let context =
+        Context::load(args.include.as_deref(), !rag_reads_stdin(args))?;
+
+    // Checked before anything is uploaded, so that a refused request
+    // leaves nothing behind on the server.
+    let context_check = if args.no_context_check {
+        None
+    } else {
+        Some(check_context_window(server, &prompt, &context)?)
+    };
+
+    let collections = find_collections(server, &args.knowledge)?;
 
     let uploads = match args.rag.as_deref() {
-        Some(patterns) => upload_rag(&config.server, patterns)?,
+        Some(patterns) => upload_rag(
+            server,
+            patterns,
+            &UploadOptions::new(args, server),
+        )?,
         None => Vec::new(),
     };
 
     // The bare UUIDs are what the chat request, journaling, and cleanup
     // all use as keys.  The paths in `uploads` are only used to label
-    // sources.
-    let rag_file_ids: Vec<String> =
-        uploads.iter().map(|u| u.id.clone()).collect();
+    // sources.  Registered uploads are kept for later runs.
+    let rag_file_ids = disposable_ids(&uploads);
+    let files = file_refs(&uploads, &collections);
 
     if log::log_enabled!(log::Level::Info) {
         log::info!(
@@ -321,8 +644,12 @@ fn process(args: &Args) -> Result<(), String> {
             _ => (),
         }
 
-        if !rag_file_ids.is_empty() {
-            log::info!("referencing {} RAG files", rag_file_ids.len());
+        if !uploads.is_empty() {
+            log::info!("referencing {} RAG files", uploads.len());
+        }
+
+        for collection in &collections {
+            log::info!("referencing knowledge {:?}", collection.name);
         }
 
         if !context.images.is_empty() {
@@ -330,165 +657,1555 @@ fn process(args: &Args) -> Result<(), String> {
         }
     }
 
-    let response = config.server.send(
-        &prompt,
-        &context,
-        &rag_file_ids,
-        !args.no_stream,
-    )?;
+    // From here on, Ctrl-C stops the response and cleans up instead of
+    // killing lui (see the interrupt module).
+    if let Err(x) = interrupt::install() {
+        if !args.keep_uploads {
+            cleanup_uploads(server, &rag_file_ids);
+        }
 
-    // Kept alive past the loop so the citation `sources` captured while
-    // streaming can be read once the response is complete.
-    let mut normalizer = OutputNormalizer::new(
-        response,
-        args.keep_think_block,
-        args.no_stream,
-    );
+        return Err(x);
+    }
 
-    while let Some(output) = normalizer.next() {
-        if args.output_json {
-            let output_json = serde_json::to_string(&output)
-                .map_err(|x| x.to_string())?;
+    if !args.keep_uploads {
+        cleanup_on_quit(server, &rag_file_ids);
+    }
 
-            println!("{output_json}");
-        } else {
-            print!("{}", output.message);
+    if let Some(ref schema) = schema {
+        let result = answer_with_schema(
+            server,
+            &prompt,
+            &context,
+            &files,
+            schema,
+            args.schema_retries,
+            context_check.as_ref(),
+        );
 
-            let _ = std::io::stdout().flush();
+        if !args.keep_uploads {
+            cleanup_uploads(server, &rag_file_ids);
         }
 
-        if log::log_enabled!(log::Level::Info) {
-            if let Some(x) = output.prompt_tokens {
-                log::info!("prompt tokens: {x}");
-            }
+        let value = result?;
 
-            if let Some(x) = output.approximate_total {
-                log::info!("total time: {x}");
-            }
+        println!("{value}");
+
+        save_exchange(
+            &mut session,
+            Exchange::new(
+                &prompt,
+                context.manifest(),
+                upload_names(&uploads),
+                &value.to_string(),
+            ),
+        );
+
+        if let Some(ref mut target) = web_chat {
+            let url = save_chat(
+                server,
+                target,
+                &prompt,
+                &context,
+                &lasting_uploads(args, &uploads),
+                &collections,
+                &value.to_string(),
+            )?;
+
+            eprintln!("chat: {url}");
         }
+
+        return Ok(());
     }
 
-    report_sources(args, &uploads, normalizer.sources())?;
+    let result =
+        send(server, &prompt, &context, &files, !args.no_stream)
+            .and_then(|response| {
+                print_response(args, response, context_check.as_ref())
+            })
+            .and_then(|answer| {
+                if let Some(ref answer) = answer {
+                    report_sources(args, &uploads, &answer.sources)?;
+                }
+
+                Ok(answer)
+            });
 
+    // The uploads are deleted whether the request succeeded or not.
     if !args.keep_uploads {
-        cleanup_uploads(&config.server, &rag_file_ids);
+        cleanup_uploads(server, &rag_file_ids);
+    }
+
+    let Some(answer) = result? else {
+        return Err("interrupted".to_string());
+    };
+
+    save_exchange(
+        &mut session,
+        Exchange::new(
+            &prompt,
+            context.manifest(),
+            upload_names(&uploads),
+            answer.message.trim_end(),
+        ),
+    );
+
+    if let Some(ref mut target) = web_chat {
+        let url = save_chat(
+            server,
+            target,
+            &prompt,
+            &context,
+            &lasting_uploads(args, &uploads),
+            &collections,
+            answer.message.trim_end(),
+        )?;
+
+        eprintln!("chat: {url}");
     }
 
     Ok(())
 }
 
-/// Warns if the local journal holds RAG uploads old enough to be
-/// abandoned leftovers (e.g. from an interrupted run), pointing the
-/// user at `--prune`.
+/// Returns the directory in which sessions are stored (see
+/// [`session::sessions_dir`]).
 ///
-/// Markers younger than the threshold are ignored so an instance of lui
-/// that is running concurrently is not flagged.
+/// # Errors
 ///
-/// Any error while checking is silently downgraded to a debug log.
-fn warn_if_stale_uploads() {
-    const STALE_AFTER: std::time::Duration =
-        std::time::Duration::from_secs(30 * 60);
+/// This function returns an error if the directory cannot be
+/// determined.
+fn sessions_dir() -> Result<PathBuf, String> {
+    session::sessions_dir().ok_or_else(|| {
+        "cannot determine the directory for sessions".to_string()
+    })
+}
 
-    let Some(dir) = journal::pending_dir() else {
-        return;
+/// Opens the session that this run adds its exchanges to: the most
+/// recent one with `--continue`, the named one with `--session`, and a
+/// new one otherwise.  The new one is [not stored](Session::unsaved)
+/// with `--no-session`, or if the sessions directory cannot be
+/// determined, which is only a warning.
+///
+/// # Errors
+///
+/// This function returns an error if the session to continue cannot be
+/// found or read.
+fn open_session(args: &Args) -> Result<Session, String> {
+    let session = if args.continue_session {
+        Session::last(&sessions_dir()?)?
+    } else if let Some(ref name) = args.session {
+        Session::open(&sessions_dir()?, name)?
+    } else if args.no_session {
+        Session::unsaved()
+    } else if let Some(dir) = session::sessions_dir() {
+        Session::create(&dir)
+    } else {
+        log::warn!(
+            "cannot determine the directory for sessions; \
+             this run is not saved in one"
+        );
+
+        Session::unsaved()
     };
 
-    match journal::count_older_than(&dir, STALE_AFTER) {
-        Ok(0) => {}
-        Ok(count) => log::warn!(
-            "{count} RAG upload(s) in {} are over 30 minutes old; \
-             run `lui --prune` to remove them",
-            dir.to_string_lossy()
-        ),
-        Err(x) => log::debug!("could not check pending uploads: {x}"),
+    if !session.exchanges.is_empty() {
+        log::info!(
+            "continuing session {} after {} turns",
+            session.name,
+            session.exchanges.len()
+        );
     }
+
+    Ok(session)
 }
 
-/// Metadata for a file uploaded for a RAG request.
-struct RagUpload {
-    /// Server-assigned UUID.
-    id: String,
-    /// The path the user gave on the command line.
-    name: String,
+/// Adds `exchange` to `session` and saves it.  The answer has already
+/// been printed by then, so failing to save is only a warning.
+fn save_exchange(session: &mut Session, exchange: Exchange) {
+    session.exchanges.push(exchange);
+
+    if !session.is_saved() {
+        return;
+    }
+
+    match session.save() {
+        Ok(()) => log::info!("saved to session {}", session.name),
+        Err(x) => log::warn!("cannot save the session: {x}"),
+    }
 }
 
-/// Surfaces the citation `sources` the server returned for a RAG query.
-/// See the [Open WebUI sources
-/// schema](crate::server#note-open-webui-sources-schema) note.
-///
-/// In text mode, the sources are appended to stdout as a Markdown-like
-/// footer.  Each is numbered with the citation number Open WebUI gave
-/// the model (so it lines up with any inline citations in the answer),
-/// and its UUID is resolved back to the original filename when it
-/// matches one of the `uploads`, falling back to
-/// [`server::source_label`] otherwise.  Uploaded files that no source
-/// cited are listed separately below.
+/// The names of the RAG uploads, as recorded in a session.
+fn upload_names(uploads: &[RagUpload]) -> Vec<String> {
+    uploads.iter().map(|u| u.name.clone()).collect()
+}
+
+/// Returns the Open WebUI chat to save the exchanges to (see
+/// [`chats`]): `Some(Some(id))` for an existing chat, `Some(None)` for a
+/// new one, and `None` if they are not to be saved.  `--save-chat`
+/// overrides the server's `save-chat` setting.
 ///
-/// In `--output-json` mode, they are emitted as a final JSON object so
-/// stdout stays valid JSON.  Each resolvable source gains a
-/// `resolved_name` field while its raw fields are preserved.
+/// # Errors
 ///
-/// If RAG files were sent but no sources came back, warn the user
-/// because the server likely ignored them.
-fn report_sources(
+/// This function returns an error, before any request is made, if the
+/// server's backend is not Open WebUI or the chat ID is not valid.
+fn chat_target(
     args: &Args,
-    uploads: &[RagUpload],
-    sources: &[serde_json::Value],
-) -> Result<(), String> {
-    // The (id, display name) pairs the resolver matches sources
-    // against.
-    let pairs: Vec<(String, String)> = uploads
-        .iter()
-        .map(|u| (u.id.clone(), u.name.clone()))
-        .collect();
-
-    let numbers = server::citation_numbers(sources);
-    let unused = unused_uploads(uploads, sources, &numbers);
+    server: &Server,
+) -> Result<Option<Option<String>>, String> {
+    let target = match args.save_chat {
+        Some(ref id) => Some(id.clone()),
+        None => server.save_chat.then_some(None),
+    };
 
-    if !sources.is_empty() {
-        if args.output_json {
-            let with_resolved: Vec<serde_json::Value> = sources
-                .iter()
-                .enumerate()
-                .map(|(index, source)| {
-                    let mut value = source.clone();
-                    if let Some(object) = value.as_object_mut() {
-                        if let Some(name) =
-                            server::resolve_source_label(source, &pairs)
-                        {
-                            object.insert(
-                                "resolved_name".to_string(),
-                                serde_json::Value::String(name),
-                            );
-                        }
-                        object.insert(
-                            "citation".to_string(),
-                            serde_json::json!(numbers[index]),
-                        );
-                        if !args.hide_excerpts {
-                            object.insert(
-                                "excerpts".to_string(),
-                                excerpts_json(source),
-                            );
-                        }
-                    }
-                    value
-                })
-                .collect();
+    if target.is_some() {
+        require_chat_history(server)?;
+    }
 
-            let json = serde_json::to_string(&serde_json::json!({
-                "sources": with_resolved,
-                "unused": unused,
-            }))
-            .map_err(|x| x.to_string())?;
+    if let Some(Some(ref id)) = target
+        && !server::is_safe_id(id)
+    {
+        return Err(format!("invalid chat ID {id:?}"));
+    }
 
-            println!("{json}");
-        } else {
-            print!("\n\n---\n");
+    Ok(target)
+}
 
-            for (index, source) in sources.iter().enumerate() {
-                // Skip if the source contributed no chunk: the model
-                // was never shown it, so it has no citation number.
+/// Checks that the server keeps a chat history, which only Open WebUI
+/// does.
+///
+/// # Errors
+///
+/// This function returns an error naming the backend if it doesn't.
+fn require_chat_history(server: &Server) -> Result<(), String> {
+    if server.backend == BackendKind::OpenWebUi {
+        Ok(())
+    } else {
+        Err(format!(
+            "the {} backend has no chat history",
+            server.backend().name()
+        ))
+    }
+}
+
+/// Adds the messages of the Open WebUI chat `id` to the history of
+/// `prompt`, after the history that it already has (from `-H` or the
+/// configuration).  The chat's system prompt is used unless `prompt`
+/// has one.
+///
+/// # Errors
+///
+/// This function returns an error if the server has no chat history,
+/// or the chat, or an image in it, cannot be fetched.
+fn import_chat(
+    server: &Server,
+    prompt: &mut prompt::Prompt,
+    id: &str,
+) -> Result<(), String> {
+    require_chat_history(server)?;
+
+    let chat = chats::get(server, id)?;
+    let messages = chats::history(&chat, server)?;
+
+    log::info!("using {} messages from chat {id}", messages.len());
+
+    if !messages.is_empty() {
+        prompt.history.get_or_insert_default().extend(messages);
+    }
+
+    if prompt.system.is_none() {
+        prompt.system = chat["params"]["system"]
+            .as_str()
+            .or_else(|| chat["system"].as_str())
+            .filter(|x| !x.trim().is_empty())
+            .map(str::to_string);
+    }
+
+    Ok(())
+}
+
+/// Saves the answer to `prompt` to the Open WebUI chat `target`: adds
+/// the question and the answer to the end of it if it exists, or
+/// creates it, with the earlier history, and sets `target` to its ID if
+/// it doesn't.  Returns the chat's URL.
+///
+/// The question carries the images in `context`, and references to the
+/// RAG `uploads` and the knowledge `collections`.  The other context
+/// files are not saved.  The `uploads` must outlive the run (see
+/// [`lasting_uploads`]), and those that are not registered are taken
+/// out of the journal once the chat is saved, so that `--prune` doesn't
+/// delete them from under it.
+///
+/// # Errors
+///
+/// This function returns an error if the chat cannot be fetched,
+/// created, or updated.
+fn save_chat(
+    server: &Server,
+    target: &mut Option<String>,
+    prompt: &prompt::Prompt,
+    context: &Context,
+    uploads: &[&RagUpload],
+    collections: &[Collection],
+    answer: &str,
+) -> Result<String, String> {
+    let model = prompt.model.as_deref();
+
+    let images = context.images.iter().map(
+        |(_, url)| serde_json::json!({ "type": "image", "url": url }),
+    );
+    let files = uploads.iter().map(|upload| {
+        serde_json::json!({
+            "type": "file",
+            "id": upload.id,
+            "name": upload.name,
+        })
+    });
+    let collections = collections.iter().map(|collection| {
+        serde_json::json!({
+            "type": "collection",
+            "id": collection.id,
+            "name": collection.name,
+        })
+    });
+
+    let question = ChatMessage {
+        role: "user".to_string(),
+        content: prompt.question.clone(),
+        model: model.map(str::to_string),
+        files: images.chain(files).chain(collections).collect(),
+    };
+    let answer = ChatMessage {
+        role: "assistant".to_string(),
+        content: answer.to_string(),
+        model: model.map(str::to_string),
+        files: Vec::new(),
+    };
+
+    let id = match target {
+        Some(id) => {
+            chats::extend(server, id, &[question, answer])?;
+
+            id.clone()
+        }
+        None => {
+            let mut messages: Vec<ChatMessage> = prompt
+                .history
+                .iter()
+                .flatten()
+                .map(|x| ChatMessage::from_message(x, model))
+                .collect();
+
+            messages.extend([question, answer]);
+
+            let id = chats::create(
+                server,
+                prompt.system.as_deref(),
+                &messages,
+            )?;
+
+            log::info!("created chat {id}");
+
+            target.insert(id).clone()
+        }
+    };
+
+    if let Some(dir) = journal::pending_dir(server.name.as_deref()) {
+        for upload in uploads.iter().filter(|x| !x.registered) {
+            if let Err(x) = journal::remove(&dir, &upload.id) {
+                log::warn!(
+                    "could not drop the record of upload {}: {x}",
+                    upload.id
+                );
+            }
+        }
+    }
+
+    chats::chat_url(server, &id)
+}
+
+/// Returns the `uploads` that outlive the run, which a saved chat can
+/// refer to: the registered ones, and with `--keep-uploads`, all.
+fn lasting_uploads<'a>(
+    args: &Args,
+    uploads: &'a [RagUpload],
+) -> Vec<&'a RagUpload> {
+    uploads
+        .iter()
+        .filter(|x| x.registered || args.keep_uploads)
+        .collect()
+}
+
+/// How often to check for Ctrl-C while waiting for input in `--chat`
+/// mode.
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Runs `--chat` mode: reads questions and slash commands (see the
+/// [`chat`] module) until the user quits, then deletes the session's
+/// RAG uploads.
+///
+/// Ctrl-C while the model is answering stops the answer and discards
+/// that turn.  Ctrl-C at the prompt ends the session.
+///
+/// # Errors
+///
+/// This function returns an error if stdin is not a terminal, or if
+/// resolving the prompt, loading the context, or the initial uploads
+/// fail.  Errors during the session are only reported.
+fn chat(
+    args: &Args,
+    config: &Config,
+    server: &Server,
+) -> Result<(), String> {
+    use std::io::IsTerminal;
+
+    if !std::io::stdin().is_terminal() {
+        return Err("--chat needs a terminal on stdin".to_string());
+    }
+
+    if rag_reads_stdin(args) {
+        return Err(
+            "--chat reads the questions from stdin, so -r - cannot be \
+             used with it"
+                .to_string(),
+        );
+    }
+
+    let params = args.params.iter().cloned().collect();
+
+    // A prompt from the configuration (`@label`) sets up the chat and
+    // provides its first question.
+    let (mut prompt, first_question) = match args.question.as_deref() {
+        Some(x) if x.starts_with('@') => {
+            let prompt = config.resolve_prompt(
+                args.history.as_deref(),
+                args.system.as_deref(),
+                Some(x),
+                args.model.as_deref(),
+                &params,
+            )?;
+            let question = prompt.question.clone();
+
+            (prompt, Some(question))
+        }
+        question => (
+            config.bare_prompt(
+                args.history.as_deref(),
+                args.system.as_deref(),
+                args.model.as_deref(),
+                &params,
+            )?,
+            question.map(str::to_string),
+        ),
+    };
+
+    if let Some(ref id) = args.from_chat {
+        import_chat(server, &mut prompt, id)?;
+    }
+
+    let mut chat = Chat::new(prompt, open_session(args)?);
+    chat.web_chat = chat_target(args, server)?;
+    let mut context = Context::load(args.include.as_deref(), true)?;
+    let collections = find_collections(server, &args.knowledge)?;
+    let mut uploads = match args.rag.as_deref() {
+        Some(patterns) => upload_rag(
+            server,
+            patterns,
+            &UploadOptions::new(args, server),
+        )?,
+        None => Vec::new(),
+    };
+
+    let result = chat_loop(
+        args,
+        server,
+        &mut chat,
+        &mut context,
+        &mut uploads,
+        &collections,
+        first_question,
+    );
+
+    // The uploads are deleted however the session ends.
+    if !args.keep_uploads {
+        cleanup_uploads(server, &disposable_ids(&uploads));
+    }
+
+    result
+}
+
+/// Reads and carries out the commands of a `--chat` session, starting
+/// with `first_question` if there is one, until the user quits.
+///
+/// # Errors
+///
+/// This function returns an error if the Ctrl-C handler cannot be
+/// installed or the commands cannot be read.  A failed command is only
+/// logged.
+fn chat_loop(
+    args: &Args,
+    server: &Server,
+    chat: &mut Chat,
+    context: &mut Context,
+    uploads: &mut Vec<RagUpload>,
+    collections: &[Collection],
+    first_question: Option<String>,
+) -> Result<(), String> {
+    interrupt::install()?;
+
+    if !args.keep_uploads {
+        cleanup_on_quit(server, &disposable_ids(uploads));
+    }
+
+    let lines = read_lines();
+    let mut command = first_question.map(Command::Ask);
+
+    if !chat.session.exchanges.is_empty() {
+        println!(
+            "Continuing session {} after {} turns.",
+            chat.session.name,
+            chat.session.exchanges.len()
+        );
+    }
+
+    println!("Type /help for the commands, /quit or Ctrl-D to quit.");
+
+    loop {
+        if let Some(command) = command.take() {
+            if command == Command::Quit {
+                break;
+            }
+
+            if let Err(x) = run_chat_command(
+                args,
+                server,
+                chat,
+                context,
+                uploads,
+                collections,
+                command,
+            ) {
+                log::error!("{x}");
+            }
+        }
+
+        print!("> ");
+        let _ = std::io::stdout().flush();
+
+        let Some(line) = next_line(&lines)? else {
+            println!();
+            break;
+        };
+
+        match chat::parse_command(&line) {
+            Ok(x) => command = x,
+            Err(x) => log::error!("{x}"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Carries out one command typed in `--chat` mode, other than `/quit`.
+///
+/// # Errors
+///
+/// This function returns an error if the command fails.  The session
+/// goes on either way.
+fn run_chat_command(
+    args: &Args,
+    server: &Server,
+    chat: &mut Chat,
+    context: &mut Context,
+    uploads: &mut Vec<RagUpload>,
+    collections: &[Collection],
+    command: Command,
+) -> Result<(), String> {
+    match command {
+        Command::Ask(question) => chat_turn(
+            args,
+            server,
+            chat,
+            context,
+            uploads,
+            collections,
+            &question,
+        )?,
+        Command::Add(pattern) => {
+            let before = context.named.len() + context.images.len();
+
+            context.load_named(&pattern)?;
+
+            println!(
+                "added {} files to the context",
+                context.named.len() + context.images.len() - before
+            );
+        }
+        Command::Rag { pattern, .. } if pattern == "-" => {
+            return Err(
+                "/rag cannot read stdin in --chat mode".to_string()
+            );
+        }
+        Command::Rag { pattern, full } => {
+            let defaults = UploadOptions::new(args, server);
+            let options = UploadOptions {
+                reuse_as: None,
+                full: full || defaults.full,
+                ..defaults
+            };
+            let added = upload_rag(server, &[pattern], &options)?;
+
+            println!("uploaded {} files for RAG", added.len());
+
+            uploads.extend(added);
+
+            if !args.keep_uploads {
+                cleanup_on_quit(server, &disposable_ids(uploads));
+            }
+        }
+        Command::Model(id) => chat.prompt.model = Some(id),
+        Command::System(system) => chat.prompt.system = system,
+        Command::Save(path) => {
+            std::fs::write(&path, chat.transcript()).map_err(|x| {
+                format!("{}: {x}", path.to_string_lossy())
+            })?;
+
+            println!("saved the conversation to {}", path.display());
+        }
+        Command::Undo => {
+            if !chat.undo() {
+                println!("nothing to undo");
+            } else if let Err(x) = chat.session.save() {
+                log::warn!("cannot save the session: {x}");
+            }
+        }
+        Command::Reset => {
+            chat.reset();
+
+            if chat.session.is_saved() {
+                println!("started session {}", chat.session.name);
+            } else {
+                println!("started a new conversation");
+            }
+        }
+        Command::Help => println!("{}", chat::HELP),
+        Command::Quit => (),
+    }
+
+    Ok(())
+}
+
+/// Asks `question` in `--chat` mode and, once the answer is complete,
+/// adds both to the conversation.
+///
+/// # Errors
+///
+/// This function returns an error if the request looks too large for
+/// the context window, or if it fails.
+fn chat_turn(
+    args: &Args,
+    server: &Server,
+    chat: &mut Chat,
+    context: &Context,
+    uploads: &[RagUpload],
+    collections: &[Collection],
+    question: &str,
+) -> Result<(), String> {
+    let prompt = chat.prompt_for(question);
+    let files = file_refs(uploads, collections);
+
+    let context_check = if args.no_context_check {
+        None
+    } else {
+        Some(check_context_window(server, &prompt, context)?)
+    };
+
+    let response =
+        send(server, &prompt, context, &files, !args.no_stream)?;
+
+    let Some(answer) =
+        print_response(args, response, context_check.as_ref())?
+    else {
+        interrupt::reset();
+        log::warn!("answer discarded");
+        return Ok(());
+    };
+
+    // Servers normally end a stream with an empty token, which
+    // `OutputNormalizer` turns into a newline, but not all do.
+    if !answer.message.ends_with('\n') {
+        println!();
+    }
+
+    report_sources(args, uploads, &answer.sources)?;
+
+    save_exchange(
+        &mut chat.session,
+        Exchange::new(
+            &prompt,
+            context.manifest(),
+            upload_names(uploads),
+            answer.message.trim_end(),
+        ),
+    );
+
+    if let Some(ref mut target) = chat.web_chat {
+        let created = target.is_none();
+        let url = save_chat(
+            server,
+            target,
+            &prompt,
+            context,
+            &lasting_uploads(args, uploads),
+            collections,
+            answer.message.trim_end(),
+        )?;
+
+        if created {
+            println!("saving the conversation to {url}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads lines from stdin on a separate thread, so that waiting for
+/// input doesn't keep lui from noticing Ctrl-C.  The channel closes at
+/// the end of input.
+fn read_lines() -> mpsc::Receiver<std::io::Result<String>> {
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+/// Waits for the next line from [`read_lines`].  Returns `None` at the
+/// end of input (Ctrl-D) or when Ctrl-C is pressed.
+///
+/// # Errors
+///
+/// This function returns an error if reading stdin fails.
+fn next_line(
+    lines: &mpsc::Receiver<std::io::Result<String>>,
+) -> Result<Option<String>, String> {
+    loop {
+        match lines.recv_timeout(INPUT_POLL_INTERVAL) {
+            Ok(line) => {
+                return line
+                    .map(Some)
+                    .map_err(|x| format!("stdin: {x}"));
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if interrupt::is_interrupted() {
+                    return Ok(None);
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Sends the request for an answer to `prompt`, and marks lui as
+/// [waiting](interrupt::set_waiting) for the first token until the
+/// caller has read it.
+///
+/// # Errors
+///
+/// This function returns an error if the request fails.
+fn send(
+    server: &Server,
+    prompt: &prompt::Prompt,
+    context: &Context,
+    files: &[FileRef],
+    stream: bool,
+) -> Result<OutputReader<ureq::BodyReader<'static>>, String> {
+    interrupt::set_waiting(true);
+
+    server
+        .backend()
+        .send(prompt, context, files, stream)
+        .inspect_err(|_| interrupt::set_waiting(false))
+}
+
+/// A complete answer, as printed by [`print_response`].
+struct Answer {
+    /// The text of the answer, normalized by [`OutputNormalizer`].
+    message: String,
+    /// The citation `sources` the server returned, if any.
+    sources: Vec<serde_json::Value>,
+}
+
+/// Prints the response as it arrives, as text or, with `--json`, as
+/// one JSON object per output, and returns the answer once it is
+/// complete.
+///
+/// Returns `None` if Ctrl-C is pressed first.  The response is then
+/// dropped, which closes the connection and makes the server stop
+/// generating.
+///
+/// # Errors
+///
+/// This function returns an error if an output cannot be serialized as
+/// JSON.
+fn print_response<T>(
+    args: &Args,
+    response: OutputReader<T>,
+    context_check: Option<&ContextCheck>,
+) -> Result<Option<Answer>, String>
+where
+    T: std::io::Read,
+{
+    // Kept alive past the loop so the citation `sources` captured while
+    // streaming can be read once the response is complete.
+    let mut normalizer = OutputNormalizer::new(
+        response,
+        args.keep_think_block,
+        args.no_stream,
+    );
+    let mut message = String::new();
+
+    for output in normalizer.by_ref() {
+        interrupt::set_waiting(false);
+
+        if interrupt::is_interrupted() {
+            return Ok(None);
+        }
+
+        if args.output_json {
+            let output_json = serde_json::to_string(&output)
+                .map_err(|x| x.to_string())?;
+
+            println!("{output_json}");
+        } else {
+            print!("{}", output.message);
+
+            let _ = std::io::stdout().flush();
+        }
+
+        message.push_str(&output.message);
+
+        if let Some(check) = context_check
+            && let Some(x) = output.prompt_tokens
+        {
+            warn_if_truncated(check, x);
+        }
+
+        if log::log_enabled!(log::Level::Info) {
+            if let Some(x) = output.prompt_tokens {
+                log::info!("prompt tokens: {x}");
+            }
+
+            if let Some(x) = output.approximate_total {
+                log::info!("total time: {x}");
+            }
+        }
+    }
+
+    interrupt::set_waiting(false);
+
+    if interrupt::is_interrupted() {
+        return Ok(None);
+    }
+
+    Ok(Some(Answer {
+        message,
+        sources: normalizer.sources().to_vec(),
+    }))
+}
+
+/// Asks the model for an answer that matches `schema`, and asks again
+/// with the validation errors, up to `retries` times, while it doesn't.
+/// Returns the first answer that matches.
+///
+/// The response is not streamed, since only a complete answer can be
+/// validated.  The `<think></think>` block is always removed before
+/// validation.
+///
+/// # Errors
+///
+/// This function returns an error if a request fails, Ctrl-C is
+/// pressed, or no answer matches the schema.
+fn answer_with_schema(
+    server: &Server,
+    prompt: &prompt::Prompt,
+    context: &Context,
+    files: &[FileRef],
+    schema: &Schema,
+    retries: u32,
+    context_check: Option<&ContextCheck>,
+) -> Result<serde_json::Value, String> {
+    let mut prompt = Cow::Borrowed(prompt);
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
+
+        let response = send(server, &prompt, context, files, false)?;
+
+        if interrupt::is_interrupted() {
+            return Err("interrupted".to_string());
+        }
+
+        let mut message = String::new();
+
+        for output in OutputNormalizer::new(response, false, true) {
+            message.push_str(&output.message);
+
+            if let Some(check) = context_check
+                && let Some(x) = output.prompt_tokens
+            {
+                warn_if_truncated(check, x);
+            }
+        }
+
+        interrupt::set_waiting(false);
+
+        let error = match schema.validate(&message) {
+            Ok(value) => return Ok(value),
+            Err(x) => x,
+        };
+
+        if attempt > retries {
+            return Err(format!(
+                "no valid answer after {attempt} attempts; {error}"
+            ));
+        }
+
+        log::warn!("attempt {attempt}: {error}; asking again");
+
+        prompt = Cow::Owned(schema::retry_prompt(
+            &prompt,
+            message.trim_end(),
+            &error,
+        ));
+    }
+}
+
+/// The result of the pre-flight context-window check, kept to compare
+/// against the prompt token count that the server reports.
+struct ContextCheck {
+    /// Estimated prompt tokens (see [`tokens`]).
+    estimate: u64,
+    /// The model's context window, if Open WebUI reports one.
+    num_ctx: Option<u64>,
+}
+
+/// How many context pieces to name when the context is too large.
+const LARGEST_CONTRIBUTORS: usize = 3;
+
+/// Estimates the prompt tokens of the request and compares the estimate
+/// against the model's `num_ctx`: the `num_ctx` parameter of the
+/// request if it sets one, and otherwise the one in the model's
+/// metadata on the server.  Warns when the request would leave less
+/// than a tenth of the window for the response.
+///
+/// A failure to look up the model only skips the comparison (with a
+/// debug log), because the check is advisory and the request may still
+/// succeed.
+///
+/// # Errors
+///
+/// This function returns an error if the estimate exceeds `num_ctx`,
+/// naming the context files that contribute the most.
+fn check_context_window(
+    server: &Server,
+    prompt: &prompt::Prompt,
+    context: &Context,
+) -> Result<ContextCheck, String> {
+    let estimate = tokens::estimate_messages(
+        &server::assemble_messages(context, prompt),
+    );
+    let model = prompt.model.as_deref().unwrap_or("");
+
+    log::info!("estimated prompt tokens: {estimate}");
+
+    let num_ctx = match prompt
+        .params
+        .get("num_ctx")
+        .and_then(serde_json::Value::as_u64)
+    {
+        Some(x) => Some(x),
+        None => match server.backend().find_model(model) {
+            Ok(x) => server::model_info(&x).context_length,
+            Err(x) => {
+                log::debug!(
+                    "could not look up num_ctx for {model:?}: {x}"
+                );
+                None
+            }
+        },
+    };
+
+    let Some(num_ctx) = num_ctx else {
+        log::debug!(
+            "no num_ctx known for {model:?}; skipping context-window \
+             check"
+        );
+        return Ok(ContextCheck {
+            estimate,
+            num_ctx: None,
+        });
+    };
+
+    let largest = || {
+        tokens::context_contributions(context)
+            .into_iter()
+            .take(LARGEST_CONTRIBUTORS)
+            .map(|(label, count)| {
+                format!("`{label}` (~{count} tokens)")
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    if estimate > num_ctx {
+        let mut message = format!(
+            "the request is estimated at {estimate} tokens, which \
+             exceeds the context window of {model} ({num_ctx} tokens); \
+             the model would silently ignore part of it"
+        );
+
+        let largest = largest();
+        if !largest.is_empty() {
+            message.push_str(&format!("; largest context: {largest}"));
+        }
+
+        message.push_str(
+            ". Shrink the context, or re-run with --no-context-check to \
+             send it anyway.",
+        );
+
+        return Err(message);
+    }
+
+    if estimate.saturating_mul(10) > num_ctx.saturating_mul(9) {
+        log::warn!(
+            "the request is estimated at {estimate} of the {num_ctx} \
+             tokens in the context window of {model}, leaving little \
+             room for the response"
+        );
+    }
+
+    Ok(ContextCheck {
+        estimate,
+        num_ctx: Some(num_ctx),
+    })
+}
+
+/// Warns if the prompt token count the server reports suggests that
+/// the prompt was truncated: either it fills the whole context window,
+/// or it is less than half of the estimate.
+fn warn_if_truncated(check: &ContextCheck, prompt_tokens: u64) {
+    if let Some(num_ctx) = check.num_ctx
+        && prompt_tokens >= num_ctx
+    {
+        log::warn!(
+            "the server reports {prompt_tokens} prompt tokens, filling \
+             the context window of {num_ctx}; the prompt was probably \
+             truncated"
+        );
+    } else if prompt_tokens.saturating_mul(2) < check.estimate {
+        log::warn!(
+            "the server reports {prompt_tokens} prompt tokens against \
+             an estimate of {}; the prompt may have been truncated",
+            check.estimate
+        );
+    }
+}
+
+/// Prints the models available on the server, one per line, as a
+/// table of ID, owner, context length, and capabilities.  In JSON mode,
+/// prints a single array of [`server::ModelInfo`] objects instead.
+///
+/// # Errors
+///
+/// This function returns an error if listing the models fails.
+fn list_models(server: &Server, json: bool) -> Result<(), String> {
+    let models: Vec<server::ModelInfo> = server
+        .backend()
+        .list_models()?
+        .iter()
+        .map(server::model_info)
+        .collect();
+
+    if json {
+        let output = serde_json::to_string(&models)
+            .map_err(|x| x.to_string())?;
+
+        println!("{output}");
+
+        return Ok(());
+    }
+
+    let rows: Vec<[String; 4]> = models
+        .iter()
+        .map(|model| {
+            [
+                model.id.clone(),
+                model
+                    .owned_by
+                    .clone()
+                    .unwrap_or_else(|| "-".to_string()),
+                model
+                    .context_length
+                    .map(|x| x.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                model.capabilities.join(","),
+            ]
+        })
+        .collect();
+
+    print!(
+        "{}",
+        format_table(["ID", "OWNER", "CONTEXT", "CAPABILITIES"], &rows)
+    );
+
+    Ok(())
+}
+
+/// Formats `rows` under `header` as left-aligned columns separated by
+/// two spaces.  Trailing whitespace is trimmed from every line.
+fn format_table<const N: usize>(
+    header: [&str; N],
+    rows: &[[String; N]],
+) -> String {
+    let mut widths = header.map(|x| x.chars().count());
+
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut result = String::new();
+
+    for row in std::iter::once(header.map(str::to_string))
+        .chain(rows.iter().cloned())
+    {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+
+        result.push_str(line.join("  ").trim_end());
+        result.push('\n');
+    }
+
+    result
+}
+
+/// The longest question printed by `--list-prompts` before truncation.
+const QUESTION_MAX_CHARS: usize = 60;
+
+/// The longest system prompt printed by `--list-prompts` before
+/// truncation.
+const SYSTEM_MAX_CHARS: usize = 40;
+
+/// Prints the configured prompts as a table of label, effective model,
+/// system prompt, and question, marking the default prompt.  In JSON
+/// mode, prints a single array with the untruncated fields instead, for
+/// shell completion and editor plugins.
+///
+/// # Errors
+///
+/// This function returns an error if the JSON cannot be serialized.
+fn list_prompts(config: &Config, json: bool) -> Result<(), String> {
+    let prompts = config.list_prompts();
+    let is_default =
+        |label: &str| config.default_prompt.as_deref() == Some(label);
+
+    if json {
+        let entries: Vec<serde_json::Value> = prompts
+            .iter()
+            .map(|prompt| {
+                serde_json::json!({
+                    "label": prompt.label,
+                    "default": is_default(&prompt.label),
+                    "model": prompt.model,
+                    "system": prompt.system,
+                    "history": prompt.history,
+                    "params": prompt.params,
+                    "question": prompt.question,
+                })
+            })
+            .collect();
+
+        let output = serde_json::to_string(&entries)
+            .map_err(|x| x.to_string())?;
+
+        println!("{output}");
+
+        return Ok(());
+    }
+
+    let rows: Vec<[String; 4]> = prompts
+        .iter()
+        .map(|prompt| {
+            let label = if is_default(&prompt.label) {
+                format!("{} (default)", prompt.label)
+            } else {
+                prompt.label.clone()
+            };
+
+            [
+                label,
+                prompt.model.clone().unwrap_or_else(|| "-".to_string()),
+                prompt
+                    .system
+                    .as_deref()
+                    .map(|x| abbreviate(x, SYSTEM_MAX_CHARS))
+                    .unwrap_or_else(|| "-".to_string()),
+                abbreviate(&prompt.question, QUESTION_MAX_CHARS),
+            ]
+        })
+        .collect();
+
+    print!(
+        "{}",
+        format_table(["LABEL", "MODEL", "SYSTEM", "QUESTION"], &rows)
+    );
+
+    Ok(())
+}
+
+/// Prints the saved sessions, most recent first, as a table with each
+/// session's number of turns, the time of its last exchange (in UTC),
+/// and its first question.  In JSON mode, prints a single array with
+/// the untruncated question and the time in seconds since the Unix
+/// epoch instead.
+///
+/// # Errors
+///
+/// This function returns an error if the sessions cannot be read or
+/// the JSON cannot be serialized.
+fn list_sessions(json: bool) -> Result<(), String> {
+    let mut sessions = session::list(&sessions_dir()?)?;
+
+    sessions.sort_by_key(|x| std::cmp::Reverse(x.updated()));
+
+    let first_question = |session: &Session| {
+        session
+            .exchanges
+            .first()
+            .map(|x| x.question.clone())
+            .unwrap_or_default()
+    };
+
+    if json {
+        let entries: Vec<serde_json::Value> = sessions
+            .iter()
+            .map(|session| {
+                serde_json::json!({
+                    "name": session.name,
+                    "turns": session.exchanges.len(),
+                    "updated": session.updated(),
+                    "question": first_question(session),
+                })
+            })
+            .collect();
+
+        let output = serde_json::to_string(&entries)
+            .map_err(|x| x.to_string())?;
+
+        println!("{output}");
+
+        return Ok(());
+    }
+
+    let rows: Vec<[String; 4]> = sessions
+        .iter()
+        .map(|session| {
+            [
+                session.name.clone(),
+                session.exchanges.len().to_string(),
+                session::format_time(session.updated()),
+                abbreviate(
+                    &first_question(session),
+                    QUESTION_MAX_CHARS,
+                ),
+            ]
+        })
+        .collect();
+
+    print!(
+        "{}",
+        format_table(["NAME", "TURNS", "UPDATED", "QUESTION"], &rows)
+    );
+
+    Ok(())
+}
+
+/// Prints the session called `name` as Markdown (see
+/// [`Session::transcript`]), or in JSON mode as it is stored.
+///
+/// # Errors
+///
+/// This function returns an error if there is no such session, it
+/// cannot be read, or the JSON cannot be serialized.
+fn show_session(name: &str, json: bool) -> Result<(), String> {
+    let session = Session::open(&sessions_dir()?, name)?;
+
+    if session.exchanges.is_empty() {
+        return Err(format!("session '{name}' not found"));
+    }
+
+    if json {
+        let output = serde_json::to_string(&session)
+            .map_err(|x| x.to_string())?;
+
+        println!("{output}");
+    } else {
+        print!("{}", session.transcript());
+    }
+
+    Ok(())
+}
+
+/// Prints the chats in Open WebUI's chat history, most recently updated
+/// first, as a table with each chat's ID, the time of its last update
+/// (in UTC), and its title.  In JSON mode, prints the entries as the
+/// server returned them instead.
+///
+/// # Errors
+///
+/// This function returns an error if the server has no chat history,
+/// listing the chats fails, or the JSON cannot be serialized.
+fn list_chats(server: &Server, json: bool) -> Result<(), String> {
+    require_chat_history(server)?;
+
+    let mut chats = chats::list(server)?;
+
+    chats.sort_by_key(|x| {
+        std::cmp::Reverse(x["updated_at"].as_u64().unwrap_or_default())
+    });
+
+    if json {
+        let output =
+            serde_json::to_string(&chats).map_err(|x| x.to_string())?;
+
+        println!("{output}");
+
+        return Ok(());
+    }
+
+    let rows: Vec<[String; 3]> = chats
+        .iter()
+        .map(|chat| {
+            [
+                chat["id"].as_str().unwrap_or("-").to_string(),
+                chat["updated_at"]
+                    .as_u64()
+                    .map(session::format_time)
+                    .unwrap_or_else(|| "-".to_string()),
+                abbreviate(
+                    chat["title"].as_str().unwrap_or_default(),
+                    QUESTION_MAX_CHARS,
+                ),
+            ]
+        })
+        .collect();
+
+    print!("{}", format_table(["ID", "UPDATED", "TITLE"], &rows));
+
+    Ok(())
+}
+
+/// Prints the details of one model, including every parameter
+/// configured for it.  In JSON mode, prints one [`server::ModelInfo`]
+/// object instead.
+///
+/// # Errors
+///
+/// This function returns an error if listing the models fails or no
+/// model has the ID `id`.
+fn show_model(
+    server: &Server,
+    id: &str,
+    json: bool,
+) -> Result<(), String> {
+    let model = server::model_info(&server.backend().find_model(id)?);
+
+    if json {
+        let output =
+            serde_json::to_string(&model).map_err(|x| x.to_string())?;
+
+        println!("{output}");
+
+        return Ok(());
+    }
+
+    let or_dash =
+        |x: Option<String>| x.unwrap_or_else(|| "-".to_string());
+
+    println!("id: {}", model.id);
+    println!("name: {}", or_dash(model.name));
+    println!("owned by: {}", or_dash(model.owned_by));
+    println!(
+        "context length: {}",
+        or_dash(model.context_length.map(|x| x.to_string()))
+    );
+    println!(
+        "capabilities: {}",
+        if model.capabilities.is_empty() {
+            "-".to_string()
+        } else {
+            model.capabilities.join(", ")
+        }
+    );
+
+    if model.params.is_empty() {
+        println!("params: -");
+    } else {
+        println!("params:");
+
+        for (key, value) in &model.params {
+            println!("  {key} = {value}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Warns if the local journal holds RAG uploads old enough to be
+/// abandoned leftovers (e.g. from an interrupted run), pointing the
+/// user at `--prune`.
+///
+/// Markers younger than the threshold are ignored so an instance of lui
+/// that is running concurrently is not flagged.
+///
+/// Any error while checking is silently downgraded to a debug log.
+fn warn_if_stale_uploads(server: &Server) {
+    const STALE_AFTER: std::time::Duration =
+        std::time::Duration::from_secs(30 * 60);
+
+    let Some(dir) = journal::pending_dir(server.name.as_deref()) else {
+        return;
+    };
+
+    let prune_command = match server.name {
+        Some(ref name) => format!("lui --prune --server {name}"),
+        None => "lui --prune".to_string(),
+    };
+
+    match journal::count_older_than(&dir, STALE_AFTER) {
+        Ok(0) => {}
+        Ok(count) => log::warn!(
+            "{count} RAG upload(s) in {} are over 30 minutes old; \
+             run `{prune_command}` to remove them",
+            dir.to_string_lossy()
+        ),
+        Err(x) => log::debug!("could not check pending uploads: {x}"),
+    }
+}
+
+/// Returns whether `-r -` uploads stdin for RAG, which then is not read
+/// as context.
+fn rag_reads_stdin(args: &Args) -> bool {
+    args.rag.iter().flatten().any(|x| x == "-")
+}
+
+/// Metadata for a file uploaded for a RAG request.
+struct RagUpload {
+    /// Server-assigned UUID.
+    id: String,
+    /// The path the user gave on the command line, or that was found in
+    /// a directory given there.
+    name: String,
+    /// Whether the upload is in the registry, and so is kept after the
+    /// query.
+    registered: bool,
+    /// Whether the model is given the whole file (`--rag-full`) rather
+    /// than the retrieved passages.
+    full: bool,
+}
+
+/// Surfaces the citation `sources` the server returned for a RAG query.
+/// See the [Open WebUI sources
+/// schema](crate::server#note-open-webui-sources-schema) note.
+///
+/// In text mode, the sources are appended to stdout as a Markdown-like
+/// footer.  Each is numbered with the citation number Open WebUI gave
+/// the model (so it lines up with any inline citations in the answer),
+/// and its UUID is resolved back to the original filename when it
+/// matches one of the `uploads`, falling back to
+/// [`server::source_label`] otherwise.  Uploaded files that no source
+/// cited are listed separately below.
+///
+/// In `--output-json` mode, they are emitted as a final JSON object so
+/// stdout stays valid JSON.  Each resolvable source gains a
+/// `resolved_name` field while its raw fields are preserved.
+///
+/// If RAG files were sent but no sources came back, warn the user
+/// because the server likely ignored them.
+fn report_sources(
+    args: &Args,
+    uploads: &[RagUpload],
+    sources: &[serde_json::Value],
+) -> Result<(), String> {
+    // The (id, display name) pairs the resolver matches sources
+    // against.
+    let pairs: Vec<(String, String)> = uploads
+        .iter()
+        .map(|u| (u.id.clone(), u.name.clone()))
+        .collect();
+
+    let numbers = server::citation_numbers(sources);
+    let unused = unused_uploads(uploads, sources, &numbers);
+    let unused_names: Vec<&str> =
+        unused.iter().map(|u| u.name.as_str()).collect();
+
+    if !sources.is_empty() {
+        if args.output_json {
+            let with_resolved: Vec<serde_json::Value> = sources
+                .iter()
+                .enumerate()
+                .map(|(index, source)| {
+                    let mut value = source.clone();
+                    if let Some(object) = value.as_object_mut() {
+                        if let Some(name) =
+                            server::resolve_source_label(source, &pairs)
+                        {
+                            object.insert(
+                                "resolved_name".to_string(),
+                                serde_json::Value::String(name),
+                            );
+                        }
+                        object.insert(
+                            "citation".to_string(),
+                            serde_json::json!(numbers[index]),
+                        );
+                        if !args.hide_excerpts {
+                            object.insert(
+                                "excerpts".to_string(),
+                                excerpts_json(source),
+                            );
+                        }
+                    }
+                    value
+                })
+                .collect();
+
+            let json = serde_json::to_string(&serde_json::json!({
+                "sources": with_resolved,
+                "unused": unused_names,
+            }))
+            .map_err(|x| x.to_string())?;
+
+            println!("{json}");
+        } else {
+            print!("\n\n---\n");
+
+            for (index, source) in sources.iter().enumerate() {
+                // Skip if the source contributed no chunk: the model
+                // was never shown it, so it has no citation number.
                 let Some(number) = numbers[index] else {
                     continue;
                 };
@@ -507,14 +2224,30 @@ fn report_sources(
             }
 
             if !unused.is_empty() {
-                let names: Vec<String> =
-                    unused.iter().map(|n| format!("`{n}`")).collect();
+                let names: Vec<String> = unused_names
+                    .iter()
+                    .map(|n| format!("`{n}`"))
+                    .collect();
                 print!(
                     "\n\n(uploaded but not retrieved: {})",
                     names.join(", ")
                 );
             }
 
+            // Retrieval may have picked the wrong passages of a file,
+            // which the model then sees in full with `--rag-full`, or
+            // in a chat, once uploaded again with `/rag-full`.
+            if unused.iter().any(|u| !u.full) {
+                print!(
+                    "\n(to give the model these files in full, {})",
+                    if args.chat {
+                        "upload them again with /rag-full"
+                    } else {
+                        "run again with --rag-full"
+                    }
+                );
+            }
+
             println!();
 
             let _ = std::io::stdout().flush();
@@ -530,7 +2263,7 @@ fn report_sources(
     Ok(())
 }
 
-/// Returns the display names of uploads that no source cited.
+/// Returns the uploads that no source cited.
 ///
 /// Matches by UUID (`source.source.id`, see the [Open WebUI sources
 /// schema](crate::server#note-open-webui-sources-schema) note in
@@ -540,7 +2273,7 @@ fn unused_uploads<'a>(
     uploads: &'a [RagUpload],
     sources: &[serde_json::Value],
     numbers: &[Option<usize>],
-) -> Vec<&'a str> {
+) -> Vec<&'a RagUpload> {
     let retrieved: Vec<&str> = sources
         .iter()
         .zip(numbers)
@@ -555,7 +2288,6 @@ fn unused_uploads<'a>(
     uploads
         .iter()
         .filter(|u| !retrieved.contains(&u.id.as_str()))
-        .map(|u| u.name.as_str())
         .collect()
 }
 
@@ -582,88 +2314,763 @@ fn print_excerpts(source: &serde_json::Value) {
             None => print!("\n   - \"{text}\""),
         }
     }
-}
+}
+
+/// Normalizes the excerpt's internal whitespace and truncates the
+/// excerpt to [`EXCERPT_MAX_CHARS`] characters.
+fn format_excerpt_text(text: &str) -> String {
+    abbreviate(text, EXCERPT_MAX_CHARS)
+}
+
+/// Collapses runs of whitespace in `text` into single spaces and
+/// truncates it to `max_chars` characters (not bytes, to avoid
+/// splitting multi-byte text mid-character), appending `...` if it was
+/// truncated.
+fn abbreviate(text: &str, max_chars: usize) -> String {
+    let collapsed =
+        text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let mut chars = collapsed.chars();
+    let truncated: String = chars.by_ref().take(max_chars).collect();
+
+    if chars.next().is_some() {
+        format!("{truncated}...")
+    } else {
+        truncated
+    }
+}
+
+/// Builds the JSON `excerpts` array for one source: the full,
+/// untruncated passages with their page labels.
+fn excerpts_json(source: &serde_json::Value) -> serde_json::Value {
+    let excerpts: Vec<serde_json::Value> =
+        server::source_excerpts(source)
+            .into_iter()
+            .map(|excerpt| {
+                serde_json::json!({
+                    "page": excerpt.page,
+                    "text": excerpt.text,
+                })
+            })
+            .collect();
+
+    serde_json::Value::Array(excerpts)
+}
+
+/// Uploads each file matched by the RAG glob patterns and records its
+/// ID in the local journal *before* the chat request is sent, so that a
+/// crash still leaves a prunable record.
+///
+/// If `options.reuse` is true, each file is looked up in the
+/// [registry](crate::registry) by its content hash instead, and only
+/// uploaded (and registered) if it has no upload on the server yet.
+/// `options.reuse_as` names the upload, which then must be the only
+/// one.  Patterns of the form `@<name>` refer to named uploads in
+/// either case.  Registered uploads are not journaled.
+///
+/// With `options.background`, the server indexes the files after the
+/// uploads return, and this function waits until it has indexed them,
+/// printing the progress to stderr.
+///
+/// # Errors
+///
+/// This function returns an error if the backend has no file store, a
+/// pattern matches no files, a file fails the checks of
+/// [`check_rag_files`], a name is unknown or its upload is gone, or an
+/// upload or its indexing fails or times out.  A failure to record an
+/// upload in the journal is only logged: the upload itself succeeded,
+/// so the query proceeds.
+fn upload_rag(
+    server: &Server,
+    patterns: &[String],
+    options: &UploadOptions,
+) -> Result<Vec<RagUpload>, String> {
+    let UploadOptions {
+        reuse, reuse_as, ..
+    } = *options;
+    let backend = require_file_store(server)?;
+
+    let (names, patterns): (Vec<String>, Vec<String>) =
+        patterns.iter().cloned().partition(|x| x.starts_with('@'));
+
+    let files = if patterns.is_empty() {
+        Vec::new()
+    } else {
+        context::expand_rag_paths(&patterns)?
+    };
+
+    check_rag_files(&files, options.max_size)?;
+
+    if let Some(name) = reuse_as {
+        registry::validate_name(name)?;
+
+        if files.len() != 1 {
+            return Err(format!(
+                "--reuse-as needs the RAG patterns to match exactly \
+                 one file, not {}",
+                files.len()
+            ));
+        }
+    }
+
+    let registry = if reuse || !names.is_empty() {
+        let dir = registry::registry_dir(server.name.as_deref())
+            .ok_or_else(|| {
+                "home directory cannot be determined".to_string()
+            })?;
+
+        Some(dir)
+    } else {
+        None
+    };
+
+    let journal = journal::pending_dir(server.name.as_deref());
+
+    if journal.is_none() {
+        log::warn!(
+            "home directory cannot be determined; \
+             uploads will not be journaled for --prune"
+        );
+    }
+
+    // The IDs of the files on the server, to check registry entries
+    // against.  Fetched only once an entry is found.
+    let mut on_server = None;
+    let mut exists = |id: &str| -> Result<bool, String> {
+        if on_server.is_none() {
+            let ids: std::collections::HashSet<String> =
+                backend.list_files()?.into_iter().collect();
+
+            on_server = Some(ids);
+        }
+
+        Ok(on_server.as_ref().is_some_and(|x| x.contains(id)))
+    };
+
+    let mut uploads = Vec::new();
+
+    if let Some(ref dir) = registry {
+        for name in &names {
+            let entry = registry::find(dir, &name[1..])?;
+
+            if !exists(&entry.id)? {
+                return Err(format!(
+                    "{name}: the upload of {} no longer exists on the \
+                     server; upload it again with --reuse-as",
+                    entry.path
+                ));
+            }
+
+            log::debug!("reusing upload {} for {name}", entry.id);
+
+            uploads.push(RagUpload {
+                id: entry.id,
+                name: entry.path,
+                registered: true,
+                full: options.full,
+            });
+        }
+    }
+
+    // One slot per file, so that the uploads keep the order of the
+    // files however the parallel uploads finish.
+    let mut slots: Vec<Option<RagUpload>> =
+        files.iter().map(|_| None).collect();
+    let mut pending = Vec::new();
+
+    for (slot, file) in slots.iter_mut().zip(&files) {
+        let path = &file.path;
+        let name = file.label.clone();
+
+        let hash = match registry {
+            Some(_) if reuse => Some(registry::hash_file(path)?),
+            _ => None,
+        };
+
+        if let Some(ref dir) = registry
+            && let Some(ref hash) = hash
+            && let Some(mut entry) = registry::get(dir, hash)?
+            && exists(&entry.id)?
+        {
+            log::debug!("reusing upload {} for {name}", entry.id);
+
+            if reuse_as.is_some() && entry.name.as_deref() != reuse_as {
+                entry.name = reuse_as.map(str::to_string);
+                registry::put(dir, &entry)?;
+            }
+
+            *slot = Some(RagUpload {
+                id: entry.id,
+                name,
+                registered: true,
+                full: options.full,
+            });
+        } else {
+            pending.push((file, name, hash));
+        }
+    }
+
+    if !pending.is_empty() {
+        log::info!(
+            "uploading {} RAG files, {} at a time",
+            pending.len(),
+            options.jobs.min(pending.len())
+        );
+    }
+
+    let registry = registry.as_deref();
+    let journal = journal.as_deref();
+    let deadline = options.background.map(|x| Instant::now() + x);
+
+    let results =
+        run_parallel(&pending, options.jobs, |(file, name, hash)| {
+            upload_rag_file(
+                server,
+                file,
+                name,
+                hash.as_deref().zip(registry),
+                journal,
+                options,
+                deadline,
+            )
+        });
+
+    let mut uploaded = Vec::new();
+    let mut error = None;
+
+    for result in results {
+        match result {
+            Ok(x) => uploaded.push(x),
+            Err(x) => {
+                error.get_or_insert(x);
+            }
+        }
+    }
+
+    // The query won't be sent, so the files that were uploaded before
+    // the failure would only linger on the server until a `--prune`.
+    // Registered uploads stay for reuse.
+    if let Some(x) = error {
+        delete_and_unjournal(
+            server,
+            journal,
+            &disposable_ids(&uploaded),
+        );
+
+        return Err(x);
+    }
+
+    let mut uploaded = uploaded.into_iter();
+
+    for slot in &mut slots {
+        if slot.is_none() {
+            *slot = uploaded.next();
+        }
+    }
+
+    uploads.extend(slots.into_iter().flatten());
+
+    Ok(uploads)
+}
+
+/// Checks the RAG `files` before any of them is uploaded: that
+/// Open WebUI has a loader for each (see [`context::rag_type`]), and
+/// that none is larger than `max_size` MB.  Warns about a file whose
+/// content doesn't match its extension.
+///
+/// # Errors
+///
+/// This function returns an error, naming the file, if a file cannot be
+/// read, is of an unsupported type, or is too large.
+fn check_rag_files(
+    files: &[context::RagFile],
+    max_size: Option<u64>,
+) -> Result<(), String> {
+    for context::RagFile { path, label, .. } in files {
+        if let Some(x) = context::rag_type(path, label)?.mismatch {
+            log::warn!("{label}: {x}");
+        }
+
+        let size = std::fs::metadata(path)
+            .map_err(|x| format!("{label}: {x}"))?
+            .len();
+
+        if let Some(max) = max_size
+            && size > max.saturating_mul(1024 * 1024)
+        {
+            return Err(format!(
+                "{label}: {:.1} MB is over the maximum upload size of \
+                 {max} MB (see --max-upload-size)",
+                size as f64 / (1024.0 * 1024.0)
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Uploads the RAG `file`, for [`upload_rag`], and registers it
+/// under `hash` in the `registry`, if given.  Until it is registered,
+/// the upload is recorded in the `journal`.
+///
+/// With a `deadline`, the server indexes the file in the background,
+/// and the upload is deleted again if indexing fails or is not done by
+/// the deadline.
+///
+/// # Errors
+///
+/// This function returns an error if the upload or its indexing fails,
+/// or indexing times out.  A failure to register or journal the upload
+/// is only logged.
+fn upload_rag_file(
+    server: &Server,
+    file: &context::RagFile,
+    name: &str,
+    registry: Option<(&str, &Path)>,
+    journal: Option<&Path>,
+    options: &UploadOptions,
+    deadline: Option<Instant>,
+) -> Result<RagUpload, String> {
+    log::debug!(
+        "uploading RAG file {:?} as {:?}",
+        file.path,
+        file.name
+    );
+
+    let backend = server.backend();
+    let id = backend.upload_file(file, deadline.is_some())?;
+
+    if let Some(dir) = journal
+        && let Err(x) = journal::add(dir, &id)
+    {
+        log::warn!("could not record upload {id}: {x}");
+    }
+
+    if let Some(deadline) = deadline
+        && let Err(x) =
+            wait_for_indexing(&*backend, &id, name, deadline)
+    {
+        delete_and_unjournal(
+            server,
+            journal,
+            std::slice::from_ref(&id),
+        );
+
+        return Err(x);
+    }
+
+    let registered = match registry {
+        Some((hash, dir)) => {
+            let entry = registry::Entry {
+                hash: hash.to_string(),
+                id: id.clone(),
+                path: name.to_string(),
+                name: options.reuse_as.map(str::to_string),
+            };
+
+            registry::put(dir, &entry)
+                .inspect_err(|x| {
+                    log::warn!("could not register upload {id}: {x}")
+                })
+                .is_ok()
+        }
+        None => false,
+    };
+
+    // An upload that could not be registered stays in the journal and
+    // is deleted after the query like any other.
+    if registered
+        && let Some(dir) = journal
+        && let Err(x) = journal::remove(dir, &id)
+    {
+        log::warn!("could not drop the record of upload {id}: {x}");
+    }
+
+    Ok(RagUpload {
+        id,
+        name: name.to_string(),
+        registered,
+        full: options.full,
+    })
+}
+
+/// Polls the server until it has indexed the upload `id` of the file
+/// `name`, printing the progress to stderr.
+///
+/// # Errors
+///
+/// This function returns an error, naming the file, if indexing fails,
+/// the status cannot be read, or the `deadline` passes.
+fn wait_for_indexing(
+    backend: &dyn Backend,
+    id: &str,
+    name: &str,
+    deadline: Instant,
+) -> Result<(), String> {
+    const POLL_EVERY: Duration = Duration::from_secs(1);
+    const REPORT_EVERY: Duration = Duration::from_secs(10);
+
+    let start = Instant::now();
+    let mut reported = start;
+
+    eprintln!("indexing {name}");
+
+    loop {
+        match backend
+            .indexing_status(id)
+            .map_err(|x| format!("{name}: {x}"))?
+        {
+            Indexing::Done => {
+                eprintln!(
+                    "indexed {name} in {}s",
+                    start.elapsed().as_secs()
+                );
+
+                return Ok(());
+            }
+            Indexing::Failed(x) => {
+                return Err(format!("{name}: indexing failed: {x}"));
+            }
+            Indexing::Pending => (),
+        }
+
+        let now = Instant::now();
+
+        if now >= deadline {
+            return Err(format!(
+                "{name}: timed out waiting for the server to index it \
+                 (see --index-timeout)"
+            ));
+        }
+
+        if now - reported >= REPORT_EVERY {
+            eprintln!(
+                "still indexing {name} ({}s)",
+                start.elapsed().as_secs()
+            );
+
+            reported = now;
+        }
+
+        std::thread::sleep(POLL_EVERY.min(deadline - now));
+    }
+}
+
+/// Calls `f` on each of the `items`, on up to `jobs` threads at a time,
+/// and returns the results in the order of the items.
+///
+/// Once an item fails, no further items are started, but the ones
+/// already started are finished, and their results returned, so that
+/// the caller can undo what succeeded.
+fn run_parallel<T: Sync, U: Send>(
+    items: &[T],
+    jobs: usize,
+    f: impl Fn(&T) -> Result<U, String> + Sync,
+) -> Vec<Result<U, String>> {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            let (next, failed, f) = (&next, &failed, &f);
+            let sender = sender.clone();
+
+            scope.spawn(move || {
+                while !failed.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+
+                    let Some(item) = items.get(index) else {
+                        break;
+                    };
+
+                    let result = f(item);
+
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+
+                    let _ = sender.send((index, result));
+                }
+            });
+        }
+    });
+
+    drop(sender);
+
+    let mut results: Vec<(usize, Result<U, String>)> =
+        receiver.into_iter().collect();
+
+    results.sort_by_key(|(index, _)| *index);
+
+    results.into_iter().map(|(_, x)| x).collect()
+}
+
+/// The IDs of the `uploads` to delete after the query: the ones that
+/// are not in the registry.
+fn disposable_ids(uploads: &[RagUpload]) -> Vec<String> {
+    uploads
+        .iter()
+        .filter(|u| !u.registered)
+        .map(|u| u.id.clone())
+        .collect()
+}
+
+/// How [`upload_rag`] uploads the RAG files.
+#[derive(Clone, Copy)]
+struct UploadOptions<'a> {
+    /// Whether to reuse registered uploads, and register new ones, by
+    /// `--reuse-uploads`, `--reuse-as`, or the server's `reuse-uploads`
+    /// setting.
+    reuse: bool,
+    /// The name to register the upload under (`--reuse-as`).
+    reuse_as: Option<&'a str>,
+    /// How many files to upload at the same time.
+    jobs: usize,
+    /// If set, the server indexes the files in the background, and
+    /// [`upload_rag`] waits at most this long for it to index all of
+    /// them.
+    background: Option<Duration>,
+    /// The size in MB above which a file is not uploaded.
+    max_size: Option<u64>,
+    /// Whether the model is given the whole files (`--rag-full`).
+    full: bool,
+}
+
+/// How many seconds [`upload_rag`] waits for background indexing,
+/// unless `--index-timeout` or the server's `index-timeout` setting
+/// says otherwise.
+const DEFAULT_INDEX_TIMEOUT: u64 = 600;
+
+impl<'a> UploadOptions<'a> {
+    fn new(args: &'a Args, server: &Server) -> Self {
+        let background =
+            args.index_in_background || server.index_in_background;
+        let timeout = args
+            .index_timeout
+            .or(server.index_timeout)
+            .unwrap_or(DEFAULT_INDEX_TIMEOUT);
+
+        Self {
+            reuse: args.reuse_uploads
+                || args.reuse_as.is_some()
+                || server.reuse_uploads,
+            reuse_as: args.reuse_as.as_deref(),
+            jobs: args.upload_jobs.into(),
+            background: background
+                .then(|| Duration::from_secs(timeout)),
+            max_size: max_upload_size(args, server),
+            full: args.rag_full,
+        }
+    }
+}
+
+/// The maximum size of a RAG upload in MB, from `--max-upload-size` or
+/// the server's `max-upload-size` setting.
+fn max_upload_size(args: &Args, server: &Server) -> Option<u64> {
+    args.max_upload_size.or(server.max_upload_size)
+}
+
+/// Looks up the knowledge collections given by `--knowledge`.
+///
+/// # Errors
+///
+/// This function returns an error if the backend has no file store, or
+/// a collection cannot be found.
+fn find_collections(
+    server: &Server,
+    names: &[String],
+) -> Result<Vec<Collection>, String> {
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    require_file_store(server)?;
+
+    names.iter().map(|x| knowledge::find(server, x)).collect()
+}
+
+/// The references to the RAG `uploads` and the knowledge `collections`
+/// that a chat request retrieves from.
+fn file_refs(
+    uploads: &[RagUpload],
+    collections: &[Collection],
+) -> Vec<FileRef> {
+    let files = uploads.iter().map(|u| {
+        if u.full {
+            FileRef::file(&u.id).in_full()
+        } else {
+            FileRef::file(&u.id)
+        }
+    });
+    let collections =
+        collections.iter().map(|c| FileRef::collection(&c.id));
+
+    files.chain(collections).collect()
+}
+
+/// Prints the knowledge collections as a table with each collection's
+/// ID, name, and description.  In JSON mode, prints the entries as the
+/// server returned them instead.
+///
+/// # Errors
+///
+/// This function returns an error if the backend has no file store,
+/// listing the collections fails, or the JSON cannot be serialized.
+fn list_knowledge(server: &Server, json: bool) -> Result<(), String> {
+    require_file_store(server)?;
+
+    let collections = knowledge::list(server)?;
+
+    if json {
+        let output = serde_json::to_string(&collections)
+            .map_err(|x| x.to_string())?;
+
+        println!("{output}");
+
+        return Ok(());
+    }
 
-/// Normalizes the excerpt's internal whitespace and truncates the
-/// excerpt to [`EXCERPT_MAX_CHARS`] characters (not bytes, to avoid
-/// splitting multi-byte text mid-character).
-fn format_excerpt_text(text: &str) -> String {
-    let collapsed =
-        text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let rows: Vec<[String; 3]> = collections
+        .iter()
+        .map(|collection| {
+            let field = |key: &str| {
+                collection[key]
+                    .as_str()
+                    .filter(|x| !x.is_empty())
+                    .unwrap_or("-")
+                    .to_string()
+            };
+
+            [
+                field("id"),
+                field("name"),
+                abbreviate(&field("description"), QUESTION_MAX_CHARS),
+            ]
+        })
+        .collect();
 
-    let mut chars = collapsed.chars();
-    let truncated: String =
-        chars.by_ref().take(EXCERPT_MAX_CHARS).collect();
+    print!("{}", format_table(["ID", "NAME", "DESCRIPTION"], &rows));
 
-    if chars.next().is_some() {
-        format!("{truncated}...")
-    } else {
-        truncated
-    }
+    Ok(())
 }
 
-/// Builds the JSON `excerpts` array for one source: the full,
-/// untruncated passages with their page labels.
-fn excerpts_json(source: &serde_json::Value) -> serde_json::Value {
-    let excerpts: Vec<serde_json::Value> =
-        server::source_excerpts(source)
-            .into_iter()
-            .map(|excerpt| {
-                serde_json::json!({
-                    "page": excerpt.page,
-                    "text": excerpt.text,
-                })
-            })
-            .collect();
+/// Creates a knowledge collection called `name` and prints its ID.
+///
+/// # Errors
+///
+/// This function returns an error if the backend has no file store or
+/// the collection cannot be created.
+fn create_knowledge(server: &Server, name: &str) -> Result<(), String> {
+    require_file_store(server)?;
 
-    serde_json::Value::Array(excerpts)
+    let collection = knowledge::create(server, name)?;
+
+    println!("{}", collection.id);
+
+    Ok(())
 }
 
-/// Uploads each file matched by the RAG glob patterns and records its
-/// ID in the local journal *before* the chat request is sent, so that a
-/// crash still leaves a prunable record.
+/// Uploads the files matched by `patterns` and adds them to the
+/// knowledge collection `name_or_id`.
+///
+/// The uploads are not journaled, since they are meant to stay.  An
+/// upload that cannot be added to the collection (e.g., because it
+/// cannot be indexed) is deleted again.
 ///
 /// # Errors
 ///
-/// This function returns an error if a pattern matches no files or an
-/// upload fails.  A failure to record an upload in the journal is only
-/// logged: the upload itself succeeded, so the query proceeds.
-fn upload_rag(
+/// This function returns an error if the backend has no file store, the
+/// collection cannot be found, a pattern matches no files, a file fails
+/// the checks of [`check_rag_files`], or a file cannot be uploaded or
+/// added.  The files added before the error stay in the collection.
+fn add_to_knowledge(
     server: &Server,
+    name_or_id: &str,
     patterns: &[String],
-) -> Result<Vec<RagUpload>, String> {
-    let paths = context::expand_rag_paths(patterns)?;
-    let dir = journal::pending_dir();
+    max_size: Option<u64>,
+) -> Result<(), String> {
+    let backend = require_file_store(server)?;
+    let collection = knowledge::find(server, name_or_id)?;
+    let files = context::expand_rag_paths(patterns)?;
 
-    if dir.is_none() {
-        log::warn!(
-            "home directory cannot be determined; \
-             uploads will not be journaled for --prune"
-        );
+    check_rag_files(&files, max_size)?;
+
+    for file in &files {
+        let label = &file.label;
+
+        log::debug!("uploading {label} to {}", collection.name);
+
+        let id = backend.upload_file(file, false)?;
+
+        if let Err(x) = knowledge::add_file(server, &collection, &id) {
+            if let Err(y) = backend.delete_file(&id) {
+                log::warn!("could not delete upload {id}: {y}");
+            }
+
+            return Err(format!("{label}: {x}"));
+        }
     }
 
-    log::info!("uploading {} RAG files", paths.len());
+    log::info!("added {} files to {}", files.len(), collection.name);
 
-    let mut uploads = Vec::new();
+    Ok(())
+}
+
+/// Removes the `files`, each given by its name or its ID, from the
+/// knowledge collection `name_or_id`.
+///
+/// # Errors
+///
+/// This function returns an error if the backend has no file store, the
+/// collection or one of the files cannot be found, or a file cannot be
+/// removed.  Nothing is removed if a file cannot be found.
+fn remove_from_knowledge(
+    server: &Server,
+    name_or_id: &str,
+    files: &[String],
+) -> Result<(), String> {
+    require_file_store(server)?;
 
-    for path in &paths {
-        log::debug!("uploading RAG file {path:?}");
+    let collection = knowledge::find(server, name_or_id)?;
+    let contents = knowledge::files(server, &collection)?;
 
-        let id = server.upload_file(path)?;
+    let mut ids = Vec::new();
 
-        if let Some(ref dir) = dir
-            && let Err(x) = journal::add(dir, &id)
-        {
-            log::warn!("could not record upload {id}: {x}");
+    for file in files {
+        let matches: Vec<&String> = contents
+            .iter()
+            .filter(|(id, name)| id == file || name == file)
+            .map(|(id, _)| id)
+            .collect();
+
+        match matches.as_slice() {
+            [id] => ids.push(*id),
+            [] => {
+                return Err(format!(
+                    "{}: no file '{file}' in the collection",
+                    collection.name
+                ));
+            }
+            _ => {
+                return Err(format!(
+                    "{}: more than one file is named '{file}'; use its \
+                     ID instead",
+                    collection.name
+                ));
+            }
         }
+    }
 
-        uploads.push(RagUpload {
-            id,
-            name: path.to_string_lossy().into_owned(),
-        });
+    for id in &ids {
+        knowledge::remove_file(server, &collection, id)?;
     }
 
-    Ok(uploads)
+    log::info!("removed {} files from {}", ids.len(), collection.name);
+
+    Ok(())
 }
 
 /// Deletes each ID from the server and, on success, drops its journal
@@ -675,10 +3082,11 @@ fn delete_and_unjournal(
     dir: Option<&Path>,
     ids: &[String],
 ) -> usize {
+    let backend = server.backend();
     let mut deleted = 0;
 
     for id in ids {
-        match server.delete_file(id) {
+        match backend.delete_file(id) {
             Ok(()) => {
                 if let Some(dir) = dir {
                     let _ = journal::remove(dir, id);
@@ -694,26 +3102,66 @@ fn delete_and_unjournal(
     deleted
 }
 
+/// Returns the server's backend if it has a file store.
+///
+/// # Errors
+///
+/// This function returns an error naming the backend if it has none,
+/// before any file is read or any request is made.
+fn require_file_store(
+    server: &Server,
+) -> Result<Box<dyn Backend + '_>, String> {
+    let backend = server.backend();
+
+    if backend.has_file_store() {
+        Ok(backend)
+    } else {
+        Err(backend::no_file_store(backend.name()))
+    }
+}
+
 /// Deletes the files uploaded for this query and clears their journal
 /// records.
 fn cleanup_uploads(server: &Server, ids: &[String]) {
-    let dir = journal::pending_dir();
+    interrupt::clear_on_quit();
+
+    let dir = journal::pending_dir(server.name.as_deref());
 
     delete_and_unjournal(server, dir.as_deref(), ids);
 }
 
+/// Has Ctrl-C delete the files uploaded for this query, `ids`, if it
+/// quits lui before lui can do so itself (see the interrupt module).
+fn cleanup_on_quit(server: &Server, ids: &[String]) {
+    let server = server.clone();
+    let ids = ids.to_vec();
+
+    interrupt::on_quit(move || cleanup_uploads(&server, &ids));
+}
+
 /// Deletes RAG files this machine uploaded but never cleaned up, using
 /// the local journal as the source of truth (so it never touches a file
-/// lui didn't create).
+/// lui didn't create).  Then drops the registry entries whose uploads no
+/// longer exist on the server.
 ///
 /// # Errors
 ///
-/// This function returns an error if the home directory or the journal
-/// cannot be read.  Individual delete failures are only warned about.
+/// This function returns an error if the backend has no file store, if
+/// the home directory, the journal, or the registry cannot be read, or
+/// if listing the files on the server fails.  Individual delete
+/// failures are only warned about.
 fn prune(server: &Server, dry_run: bool) -> Result<(), String> {
-    let dir = journal::pending_dir().ok_or_else(|| {
-        "home directory cannot be determined".to_string()
-    })?;
+    require_file_store(server)?;
+
+    prune_journal(server, dry_run)?;
+    prune_registry(server, dry_run)
+}
+
+/// Deletes the files in the journal, for [`prune`].
+fn prune_journal(server: &Server, dry_run: bool) -> Result<(), String> {
+    let dir = journal::pending_dir(server.name.as_deref()).ok_or_else(
+        || "home directory cannot be determined".to_string(),
+    )?;
 
     let ids = journal::load(&dir)?;
 
@@ -732,26 +3180,69 @@ fn prune(server: &Server, dry_run: bool) -> Result<(), String> {
     Ok(())
 }
 
+/// Drops the stale registry entries, for [`prune`].  Nothing on the
+/// server is deleted.
+fn prune_registry(
+    server: &Server,
+    dry_run: bool,
+) -> Result<(), String> {
+    let dir = registry::registry_dir(server.name.as_deref())
+        .ok_or_else(|| {
+            "home directory cannot be determined".to_string()
+        })?;
+
+    let entries = registry::load(&dir)?;
+
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let on_server: std::collections::HashSet<String> =
+        server.backend().list_files()?.into_iter().collect();
+
+    let stale: Vec<registry::Entry> = entries
+        .into_iter()
+        .filter(|x| !on_server.contains(&x.id))
+        .collect();
+
+    if dry_run {
+        log::info!("{} registry entries would be dropped", stale.len());
+        return Ok(());
+    }
+
+    for entry in &stale {
+        log::debug!("dropping registry entry for {}", entry.path);
+
+        registry::remove(&dir, &entry.hash)?;
+    }
+
+    log::info!("dropped {} registry entries", stale.len());
+
+    Ok(())
+}
+
 /// Deletes every file the user can access on the server.  Destructive
 /// and irreversible, so it refuses to run without `--yes` unless this is
 /// a dry run.
 ///
 /// # Errors
 ///
-/// This function returns an error if `--yes` was not given, or if
-/// listing the files fails.  Individual delete failures are only warned
-/// about.
+/// This function returns an error if the backend has no file store,
+/// `--yes` was not given, or listing the files fails.  Individual
+/// delete failures are only warned about.
 fn prune_all(
     server: &Server,
     yes: bool,
     dry_run: bool,
 ) -> Result<(), String> {
+    let backend = require_file_store(server)?;
+
     if !dry_run {
         // Check confirmation before any network call.
         prune_all_confirmed(yes)?;
     }
 
-    let ids = server.list_files()?;
+    let ids = backend.list_files()?;
 
     if dry_run {
         for id in &ids {
@@ -761,7 +3252,7 @@ fn prune_all(
         return Ok(());
     }
 
-    let dir = journal::pending_dir();
+    let dir = journal::pending_dir(server.name.as_deref());
     let deleted = delete_and_unjournal(server, dir.as_deref(), &ids);
 
     log::info!("deleted {deleted} of {} files", ids.len());
@@ -807,6 +3298,10 @@ fn main() {
 
     match process(&args) {
         Ok(_) => std::process::exit(0),
+        // Already reported by the Ctrl-C handler.
+        Err(_) if interrupt::is_interrupted() => {
+            std::process::exit(interrupt::EXIT_STATUS)
+        }
         Err(x) => {
             log::error!("{x}");
             std::process::exit(1);
@@ -859,6 +3354,8 @@ mod tests {
         let upload = |id: &str, name: &str| RagUpload {
             id: id.to_string(),
             name: name.to_string(),
+            registered: false,
+            full: false,
         };
 
         // Two uploads with the same basename `report.pdf` but distinct
@@ -876,10 +3373,32 @@ mod tests {
         })];
         let numbers = vec![Some(1)];
 
-        assert_eq!(
-            unused_uploads(&uploads, &sources, &numbers),
-            vec!["dir_a/report.pdf"]
-        );
+        let unused = unused_uploads(&uploads, &sources, &numbers);
+
+        assert_eq!(unused.len(), 1);
+        assert_eq!(unused[0].name, "dir_a/report.pdf");
+    }
+
+    #[test]
+    fn lasting_uploads_are_registered_or_kept() {
+        let upload = |id: &str, registered| RagUpload {
+            id: id.to_string(),
+            name: format!("{id}.pdf"),
+            registered,
+            full: false,
+        };
+        let uploads = [upload("a", false), upload("b", true)];
+        let ids = |args: &[&str]| -> Vec<String> {
+            let args = Args::try_parse_from(args).unwrap();
+
+            lasting_uploads(&args, &uploads)
+                .iter()
+                .map(|x| x.id.clone())
+                .collect()
+        };
+
+        assert_eq!(ids(&["lui", "q"]), ["b"]);
+        assert_eq!(ids(&["lui", "--keep-uploads", "q"]), ["a", "b"]);
     }
 
     #[test]
@@ -891,6 +3410,8 @@ mod tests {
         let uploads = vec![RagUpload {
             id: "id-a".to_string(),
             name: "notes.txt".to_string(),
+            registered: false,
+            full: false,
         }];
         let sources = vec![json!({
             "source": {"id": "id-a"},
@@ -898,12 +3419,58 @@ mod tests {
             "metadata": [],
         })];
 
+        let unused = unused_uploads(&uploads, &sources, &[None]);
+
+        assert_eq!(unused.len(), 1);
+        assert_eq!(unused[0].name, "notes.txt");
+    }
+
+    #[test]
+    fn format_table_aligns_columns() {
+        let rows = vec![
+            [
+                "gemma3:27b".to_string(),
+                "ollama".to_string(),
+                "".to_string(),
+            ],
+            ["phi4".to_string(), "-".to_string(), "vision".to_string()],
+        ];
+
         assert_eq!(
-            unused_uploads(&uploads, &sources, &[None]),
-            vec!["notes.txt"]
+            format_table(["ID", "OWNER", "CAPABILITIES"], &rows),
+            "ID          OWNER   CAPABILITIES\n\
+             gemma3:27b  ollama\n\
+             phi4        -       vision\n"
         );
     }
 
+    #[test]
+    fn abbreviate_counts_characters_not_bytes() {
+        assert_eq!(abbreviate("概括概括", 2), "概括...");
+        assert_eq!(abbreviate("a\n\nb", 3), "a b");
+    }
+
+    #[test]
+    fn model_listing_is_a_standalone_operation() {
+        use clap::Parser;
+
+        let ok = |a: &[&str]| Args::try_parse_from(a).is_ok();
+        let err = |a: &[&str]| Args::try_parse_from(a).is_err();
+
+        assert!(ok(&["lui", "--list-models"]));
+        assert!(ok(&["lui", "--list-models", "--json", "-P", "gpu"]));
+        assert!(ok(&["lui", "--show-model", "gemma3:27b", "-j"]));
+
+        assert!(err(&["lui", "--list-models", "hello"]));
+        assert!(err(&["lui", "--list-models", "--prune"]));
+        assert!(err(&["lui", "--show-model", "x", "-m", "gemma"]));
+        assert!(err(&["lui", "--list-models", "--show-model", "x"]));
+
+        assert!(ok(&["lui", "--list-prompts", "--json"]));
+        assert!(err(&["lui", "--list-prompts", "@tldr"]));
+        assert!(err(&["lui", "--list-prompts", "--list-models"]));
+    }
+
     #[test]
     fn prune_all_requires_confirmation() {
         // Without --yes the guard fails, and it does so before any
@@ -912,6 +3479,252 @@ mod tests {
         assert!(prune_all_confirmed(true).is_ok());
     }
 
+    #[test]
+    fn schema_options_parse() {
+        use clap::Parser;
+
+        let ok = |a: &[&str]| Args::try_parse_from(a).is_ok();
+        let err = |a: &[&str]| Args::try_parse_from(a).is_err();
+
+        let args =
+            Args::try_parse_from(["lui", "--schema", "s.json", "q"])
+                .unwrap();
+        assert_eq!(args.schema_retries, 2);
+
+        assert!(ok(&[
+            "lui",
+            "--schema",
+            "s.json",
+            "--schema-retries",
+            "0"
+        ]));
+        assert!(err(&["lui", "--schema-retries", "3", "q"]));
+        assert!(err(&["lui", "--schema", "s.json", "--json", "q"]));
+        assert!(err(&["lui", "--prune", "--schema", "s.json"]));
+    }
+
+    #[test]
+    fn chat_takes_prompting_options() {
+        use clap::Parser;
+
+        let ok = |a: &[&str]| Args::try_parse_from(a).is_ok();
+        let err = |a: &[&str]| Args::try_parse_from(a).is_err();
+
+        assert!(ok(&["lui", "--chat"]));
+        assert!(ok(&[
+            "lui", "--chat", "-i", "src/*.rs", "-m", "m", "q"
+        ]));
+        assert!(err(&["lui", "--chat", "--schema", "s.json"]));
+        assert!(err(&["lui", "--chat", "--json"]));
+        assert!(err(&["lui", "--chat", "--prune"]));
+    }
+
+    #[test]
+    fn session_options_parse() {
+        use clap::Parser;
+
+        let ok = |a: &[&str]| Args::try_parse_from(a).is_ok();
+        let err = |a: &[&str]| Args::try_parse_from(a).is_err();
+
+        assert!(ok(&["lui", "-c", "and then?"]));
+        assert!(ok(&["lui", "--session", "refactor", "--chat"]));
+        assert!(err(&["lui", "--continue", "--session", "x", "q"]));
+        assert!(ok(&["lui", "--no-session", "q"]));
+        assert!(err(&["lui", "--no-session", "--continue", "q"]));
+        assert!(err(&["lui", "--no-session", "--session", "x", "q"]));
+        assert!(err(&["lui", "--continue", "--prune"]));
+
+        assert!(ok(&["lui", "--list-sessions", "--json"]));
+        assert!(ok(&["lui", "--show-session", "refactor", "-j"]));
+        assert!(ok(&["lui", "--rm-session", "refactor"]));
+        assert!(err(&["lui", "--list-sessions", "q"]));
+        assert!(err(&["lui", "--show-session", "x", "--continue"]));
+        assert!(err(&["lui", "--rm-session", "x", "--list-sessions"]));
+    }
+
+    #[test]
+    fn save_chat_takes_an_optional_id() {
+        use clap::Parser;
+
+        let args =
+            Args::try_parse_from(["lui", "q", "--save-chat"]).unwrap();
+        assert_eq!(args.save_chat, Some(None));
+        assert_eq!(args.question.as_deref(), Some("q"));
+
+        let args = Args::try_parse_from([
+            "lui",
+            "--save-chat",
+            "b9733e9c",
+            "--",
+            "q",
+        ])
+        .unwrap();
+        assert_eq!(args.save_chat, Some(Some("b9733e9c".to_string())));
+
+        assert!(
+            Args::try_parse_from([
+                "lui",
+                "--list-models",
+                "--save-chat"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn chat_history_options_parse() {
+        use clap::Parser;
+
+        let ok = |a: &[&str]| Args::try_parse_from(a).is_ok();
+        let err = |a: &[&str]| Args::try_parse_from(a).is_err();
+
+        assert!(ok(&["lui", "--from-chat", "c1", "-i", "x.rs", "q"]));
+        assert!(ok(&["lui", "--from-chat", "c1", "--save-chat", "c1"]));
+        assert!(err(&["lui", "--from-chat", "c1", "--list-models"]));
+
+        assert!(ok(&["lui", "--list-chats", "-j", "-P", "gpu"]));
+        assert!(err(&["lui", "--list-chats", "q"]));
+        assert!(err(&["lui", "--list-chats", "--from-chat", "c1"]));
+    }
+
+    #[test]
+    fn run_parallel_keeps_the_order_and_stops_at_an_error() {
+        let items: Vec<u64> = (0..20).collect();
+
+        let squares = run_parallel(&items, 4, |x| {
+            // Make the later items finish first.
+            std::thread::sleep(Duration::from_millis(20 - x));
+            Ok(x * x)
+        })
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+        assert_eq!(
+            squares,
+            items.iter().map(|x| x * x).collect::<Vec<_>>()
+        );
+
+        let started = std::sync::atomic::AtomicUsize::new(0);
+
+        let result = run_parallel(&items, 2, |x| {
+            started.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            std::thread::sleep(Duration::from_millis(5));
+
+            if *x == 3 {
+                Err(format!("item {x}"))
+            } else {
+                Ok(*x)
+            }
+        });
+
+        // The items that ran are all reported, the failed one among
+        // them, so that what succeeded can be undone.
+        assert!(result.len() < items.len());
+        assert_eq!(result.len(), started.into_inner());
+        assert_eq!(result[3], Err("item 3".to_string()));
+        assert_eq!(result[2], Ok(2));
+
+        assert_eq!(run_parallel(&[] as &[u64], 4, |x| Ok(*x)), vec![]);
+    }
+
+    #[test]
+    fn index_timeout_comes_from_the_flag_or_the_server() {
+        use clap::Parser;
+
+        let background = |a: &[&str], server: &Server| {
+            let args = Args::try_parse_from(a).unwrap();
+
+            UploadOptions::new(&args, server).background
+        };
+
+        let mut server = Server::default();
+
+        assert_eq!(background(&["lui", "q"], &server), None);
+        assert_eq!(
+            background(&["lui", "--index-in-background", "q"], &server),
+            Some(Duration::from_secs(DEFAULT_INDEX_TIMEOUT))
+        );
+
+        server.index_in_background = true;
+        server.index_timeout = Some(60);
+
+        assert_eq!(
+            background(&["lui", "q"], &server),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            background(&["lui", "--index-timeout", "5", "q"], &server),
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn upload_jobs_are_bounded() {
+        use clap::Parser;
+
+        let jobs = |a: &[&str]| {
+            Args::try_parse_from(a).map(|x| x.upload_jobs).ok()
+        };
+
+        assert_eq!(jobs(&["lui", "q"]), Some(4));
+        assert_eq!(jobs(&["lui", "--upload-jobs", "8", "q"]), Some(8));
+        assert_eq!(jobs(&["lui", "--upload-jobs", "0", "q"]), None);
+        assert_eq!(jobs(&["lui", "--upload-jobs", "33", "q"]), None);
+    }
+
+    #[test]
+    fn knowledge_options_parse() {
+        use clap::Parser;
+
+        let ok = |a: &[&str]| Args::try_parse_from(a).is_ok();
+        let err = |a: &[&str]| Args::try_parse_from(a).is_err();
+
+        let args = Args::try_parse_from([
+            "lui",
+            "--knowledge",
+            "Handbook",
+            "--knowledge",
+            "kb-2",
+            "-r",
+            "notes.md",
+            "--",
+            "q",
+        ])
+        .unwrap();
+        assert_eq!(args.knowledge, vec!["Handbook", "kb-2"]);
+
+        assert!(ok(&["lui", "--list-knowledge", "-j"]));
+        assert!(ok(&["lui", "--create-knowledge", "Handbook"]));
+        assert!(ok(&[
+            "lui",
+            "--add-to-knowledge",
+            "Handbook",
+            "*.pdf"
+        ]));
+        assert!(ok(&[
+            "lui",
+            "--remove-from-knowledge",
+            "Handbook",
+            "a.pdf",
+            "b.pdf",
+        ]));
+
+        assert!(err(&["lui", "--add-to-knowledge", "Handbook"]));
+        assert!(err(&["lui", "--list-knowledge", "--knowledge", "x"]));
+        assert!(err(&["lui", "--create-knowledge", "x", "--prune"]));
+
+        // The size limit applies to knowledge uploads as well.
+        assert!(ok(&[
+            "lui",
+            "--add-to-knowledge",
+            "Handbook",
+            "a.pdf",
+            "--max-upload-size",
+            "5",
+        ]));
+    }
+
     #[test]
     fn prune_is_a_standalone_operation() {
         use clap::Parser;
@@ -930,7 +3743,13 @@ mod tests {
         assert!(err(&["lui", "--prune", "-i", "x.txt"]));
         assert!(err(&["lui", "--prune", "-m", "gemma"]));
         assert!(err(&["lui", "--prune", "--keep-uploads"]));
+        assert!(err(&["lui", "--prune", "--reuse-as", "x"]));
+        assert!(err(&["lui", "--prune", "--upload-jobs", "2"]));
+        assert!(err(&["lui", "--prune", "--max-upload-size", "5"]));
         assert!(err(&["lui", "--prune-all", "--yes", "hello"]));
+        assert!(err(&["lui", "--list-models", "--index-timeout", "5"]));
+        assert!(err(&["lui", "--list-chats", "--rag-full"]));
+        assert!(err(&["lui", "--rag-full", "q"]));
 
         // The two prune modes are mutually exclusive, and --yes is
         // only allowed with --prune-all.
@@ -942,6 +3761,16 @@ mod tests {
         assert!(ok(&["lui", "--prune", "--dry-run-prune"]));
         assert!(ok(&["lui", "--prune-all", "--dry-run-prune"]));
 
+        // A server profile can be chosen for any operation.
+        assert!(ok(&["lui", "--prune", "-P", "staging"]));
+        assert!(ok(&[
+            "lui",
+            "--prune-all",
+            "--yes",
+            "--server",
+            "gpu"
+        ]));
+
         // A normal prompt with prompting options is still fine.
         assert!(ok(&["lui", "hello", "-r", "x.pdf", "-m", "gemma"]));
     }
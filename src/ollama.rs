@@ -0,0 +1,498 @@
+//! The backend for Ollama's native API, which lui talks to directly
+//! instead of through Open WebUI.
+//!
+//! Ollama's `/api/chat` differs from the OpenAI-style API in three ways
+//! that matter here:
+//!
+//! 1. A streamed response is newline-delimited JSON, one object per
+//!    line, rather than server-sent events (see [`ChunkIter`]).
+//! 2. Images go in a message's `images` array as plain base64 rather
+//!    than as `image_url` content parts.
+//! 3. Token counts and timings come in the last object (`done: true`)
+//!    as `prompt_eval_count` and `total_duration`, not in `usage`.
+//!
+//! Responses are mapped into [`Output`] so that the rest of lui does
+//! not need to know which backend produced them.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::io::{BufRead, BufReader};
+use ureq::BodyReader;
+
+use crate::backend::{Backend, no_file_store};
+use crate::context::Context;
+use crate::prompt::{Params, Prompt};
+use crate::server::{
+    ContentPart, FileRef, Message, MessageContent, Output, OutputIter,
+    OutputReader, Server, assemble_messages,
+};
+
+pub struct Ollama<'a> {
+    server: &'a Server,
+}
+
+impl<'a> Ollama<'a> {
+    pub fn new(server: &'a Server) -> Self {
+        Self { server }
+    }
+}
+
+impl Backend for Ollama<'_> {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    /// Sends a prompt and a context to `/api/chat`.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if there are RAG files to attach,
+    /// no model is specified, the request fails, or the response is
+    /// malformed.
+    fn send(
+        &self,
+        prompt: &Prompt,
+        context: &Context,
+        files: &[FileRef],
+        stream: bool,
+    ) -> Result<OutputReader<BodyReader<'static>>, String> {
+        if !files.is_empty() {
+            return Err(no_file_store(self.name()));
+        }
+
+        let request = ChatRequest {
+            model: prompt
+                .model
+                .as_deref()
+                .ok_or_else(|| "no model specified".to_string())?
+                .to_string(),
+            messages: assemble_messages(context, prompt)
+                .into_iter()
+                .map(ChatMessage::from)
+                .collect(),
+            stream,
+            format: prompt.schema.clone(),
+            options: ollama_options(&prompt.params),
+        };
+
+        let response = self
+            .server
+            .agent()?
+            .post(&self.server.url("/api/chat")?)
+            .header("Authorization", &self.server.bearer()?)
+            .send_json(&request)
+            .map_err(|x| format!("{x}"))?;
+
+        let body_reader = response.into_body().into_reader();
+
+        if stream {
+            Ok(OutputReader::Chunked(ChunkIter::new(BufReader::new(
+                body_reader,
+            ))))
+        } else {
+            let value: Value = serde_json::from_reader(body_reader)
+                .map_err(|x| format!("{x}"))?;
+
+            Ok(OutputReader::Complete(OutputIter::new(chunk_output(
+                &value,
+            )?)))
+        }
+    }
+
+    /// Lists the local models from `/api/tags`.
+    ///
+    /// The tags carry no context window; see [`Ollama::find_model`]
+    /// for that.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the request fails or the response
+    /// has no `models` array.
+    fn list_models(&self) -> Result<Vec<Value>, String> {
+        let value: Value = self
+            .server
+            .agent()?
+            .get(&self.server.url("/api/tags")?)
+            .header("Authorization", &self.server.bearer()?)
+            .call()
+            .map_err(|x| format!("{x}"))?
+            .into_body()
+            .read_json()
+            .map_err(|x| format!("{x}"))?;
+
+        let models = value["models"]
+            .as_array()
+            .ok_or_else(|| "malformed model list".to_string())?;
+
+        Ok(models
+            .iter()
+            .map(|model| {
+                serde_json::json!({
+                    "id": model["name"],
+                    "owned_by": "ollama",
+                })
+            })
+            .collect())
+    }
+
+    /// Looks up one model with `/api/show`, which, unlike
+    /// `/api/tags`, reports its parameters and capabilities.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the request fails (including
+    /// when Ollama doesn't have the model) or the response is not JSON.
+    fn find_model(&self, id: &str) -> Result<Value, String> {
+        let value: Value = self
+            .server
+            .agent()?
+            .post(&self.server.url("/api/show")?)
+            .header("Authorization", &self.server.bearer()?)
+            .send_json(serde_json::json!({ "model": id }))
+            .map_err(|x| format!("model '{id}': {x}"))?
+            .into_body()
+            .read_json()
+            .map_err(|x| format!("{x}"))?;
+
+        Ok(normalize_show(id, &value))
+    }
+}
+
+/// Converts the response of `/api/show` into the shape of an Open
+/// WebUI model entry (see [`crate::server::model_info`]).
+///
+/// `parameters` is the Modelfile's `PARAMETER` lines as text, e.g.
+/// `"num_ctx 8192\nstop \"<|eot|>\""`.  Only numeric values are kept.
+/// A model without `num_ctx` gets none: it runs with Ollama's default
+/// window, which depends on the Ollama version and setup, not the
+/// model's trained `context_length`.
+fn normalize_show(id: &str, show: &Value) -> Value {
+    let mut params = Map::new();
+
+    for line in show["parameters"].as_str().unwrap_or("").lines() {
+        if let Some((key, value)) = line.split_once(char::is_whitespace)
+            && let Ok(number) =
+                value.trim().parse::<serde_json::Number>()
+        {
+            params.insert(key.to_string(), Value::Number(number));
+        }
+    }
+
+    let capabilities: Map<String, Value> = show["capabilities"]
+        .as_array()
+        .map(|names| {
+            names
+                .iter()
+                .filter_map(Value::as_str)
+                .map(|name| (name.to_string(), Value::Bool(true)))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    serde_json::json!({
+        "id": id,
+        "owned_by": "ollama",
+        "info": {
+            "params": params,
+            "meta": { "capabilities": capabilities },
+        },
+    })
+}
+
+/// Converts generation parameters to Ollama's `options`, renaming
+/// OpenAI's `max_tokens` to Ollama's `num_predict` so that the same
+/// prompt works with every backend.
+fn ollama_options(params: &Params) -> Params {
+    params
+        .iter()
+        .map(|(key, value)| {
+            let key = match key.as_str() {
+                "max_tokens" => "num_predict".to_string(),
+                _ => key.clone(),
+            };
+
+            (key, value.clone())
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize)]
+struct ChatRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    stream: bool,
+
+    /// A JSON schema that constrains the answer (see
+    /// [`crate::schema`]).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<Value>,
+
+    /// Model options for this request (e.g., `num_ctx`).  Skipped when
+    /// empty so that the Modelfile's settings apply.
+    #[serde(skip_serializing_if = "Params::is_empty")]
+    options: Params,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct ChatMessage {
+    role: String,
+    content: String,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
+}
+
+impl From<Message> for ChatMessage {
+    /// Joins the text parts of a message and moves its images into
+    /// `images`, stripping the `data:<type>;base64,` prefix that the
+    /// OpenAI format requires and Ollama rejects.
+    fn from(message: Message) -> Self {
+        match message.content {
+            MessageContent::Text(content) => Self {
+                role: message.role,
+                content,
+                images: Vec::new(),
+            },
+            MessageContent::Parts(parts) => {
+                let mut texts = Vec::new();
+                let mut images = Vec::new();
+
+                for part in parts {
+                    match part {
+                        ContentPart::Text { text } => texts.push(text),
+                        ContentPart::ImageUrl { image_url } => {
+                            let url = image_url.url;
+
+                            images.push(match url.split_once(",") {
+                                Some((_, data)) => data.to_string(),
+                                None => url,
+                            });
+                        }
+                    }
+                }
+
+                Self {
+                    role: message.role,
+                    content: texts.join("\n\n"),
+                    images,
+                }
+            }
+        }
+    }
+}
+
+/// Iterates over the chunks of a streamed `/api/chat` response.
+pub struct ChunkIter<T>
+where
+    T: std::io::Read,
+{
+    reader: BufReader<T>,
+}
+
+impl<T> ChunkIter<T>
+where
+    T: std::io::Read,
+{
+    pub fn new(reader: BufReader<T>) -> Self {
+        Self { reader }
+    }
+}
+
+impl<T: std::io::Read> Iterator for ChunkIter<T> {
+    type Item = Output;
+
+    /// Reads the next line of the response and maps it to an `Output`.
+    ///
+    /// # Errors
+    ///
+    /// This method logs an error and ends the iteration if the server
+    /// sends a line that is not JSON, or reports an error.
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buffer = String::new();
+
+        while let Ok(length) = self.reader.read_line(&mut buffer) {
+            if length == 0 {
+                return None;
+            }
+
+            let line = buffer.trim();
+
+            if line.is_empty() {
+                buffer.clear();
+                continue;
+            }
+
+            let Ok(value): Result<Value, _> =
+                serde_json::from_str(line)
+            else {
+                log::error!("server sent bad JSON: {line:?}");
+                return None;
+            };
+
+            return match chunk_output(&value) {
+                Ok(output) => Some(output),
+                Err(x) => {
+                    log::error!("{x}");
+                    None
+                }
+            };
+        }
+
+        None
+    }
+}
+
+/// Maps one response object, streamed or complete, to an `Output`.
+///
+/// # Errors
+///
+/// This function returns an error if the object carries an `error`
+/// message or has no message content.
+fn chunk_output(value: &Value) -> Result<Output, String> {
+    if let Some(error) = value["error"].as_str() {
+        return Err(format!("ollama: {error}"));
+    }
+
+    Ok(Output {
+        message: value["message"]["content"]
+            .as_str()
+            .ok_or_else(|| "malformed response".to_string())?
+            .to_string(),
+        prompt_tokens: value["prompt_eval_count"].as_u64(),
+        approximate_total: value["total_duration"]
+            .as_u64()
+            .map(format_duration),
+    })
+}
+
+/// Formats nanoseconds the way Open WebUI formats `approximate_total`,
+/// e.g. `0h1m5s`.
+fn format_duration(nanoseconds: u64) -> String {
+    let seconds = nanoseconds / 1_000_000_000;
+
+    format!(
+        "{}h{}m{}s",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::ImageUrl;
+    use serde_json::json;
+
+    #[test]
+    fn chunk_iter_reads_ndjson() {
+        let body = concat!(
+            r#"{"message":{"role":"assistant","content":"Hel"},"done":false}"#,
+            "\n",
+            r#"{"message":{"role":"assistant","content":"lo"},"done":false}"#,
+            "\n\n",
+            r#"{"message":{"role":"assistant","content":""},"done":true,"#,
+            r#""prompt_eval_count":26,"total_duration":65000000000}"#,
+            "\n",
+        );
+
+        let outputs: Vec<Output> =
+            ChunkIter::new(BufReader::new(body.as_bytes())).collect();
+
+        assert_eq!(
+            outputs,
+            vec![
+                Output {
+                    message: "Hel".to_string(),
+                    prompt_tokens: None,
+                    approximate_total: None,
+                },
+                Output {
+                    message: "lo".to_string(),
+                    prompt_tokens: None,
+                    approximate_total: None,
+                },
+                Output {
+                    message: "".to_string(),
+                    prompt_tokens: Some(26),
+                    approximate_total: Some("0h1m5s".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn chunk_iter_stops_on_error() {
+        let body = concat!(
+            r#"{"message":{"role":"assistant","content":"a"}}"#,
+            "\n",
+            r#"{"error":"model ran out of memory"}"#,
+            "\n",
+            r#"{"message":{"role":"assistant","content":"b"}}"#,
+            "\n",
+        );
+
+        let outputs: Vec<Output> =
+            ChunkIter::new(BufReader::new(body.as_bytes())).collect();
+
+        assert_eq!(outputs.len(), 1);
+    }
+
+    #[test]
+    fn ollama_options_renames_max_tokens() {
+        let params = Params::from([
+            ("max_tokens".to_string(), json!(256)),
+            ("temperature".to_string(), json!(0)),
+        ]);
+
+        assert_eq!(
+            ollama_options(&params),
+            Params::from([
+                ("num_predict".to_string(), json!(256)),
+                ("temperature".to_string(), json!(0)),
+            ])
+        );
+    }
+
+    #[test]
+    fn chat_message_moves_images() {
+        let message = Message {
+            role: "user".to_string(),
+            content: MessageContent::Parts(vec![
+                ContentPart::Text {
+                    text: "What is this?".to_string(),
+                },
+                ContentPart::ImageUrl {
+                    image_url: ImageUrl {
+                        url: "data:image/png;base64,iVBOR".to_string(),
+                    },
+                },
+            ]),
+        };
+
+        assert_eq!(
+            ChatMessage::from(message),
+            ChatMessage {
+                role: "user".to_string(),
+                content: "What is this?".to_string(),
+                images: vec!["iVBOR".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn normalize_show_reads_parameters_and_capabilities() {
+        let show = json!({
+            "parameters": "num_ctx 8192\nstop \"<end>\"\ntemperature 0.2",
+            "capabilities": ["completion", "vision"],
+        });
+
+        let info =
+            crate::server::model_info(&normalize_show("gemma3", &show));
+
+        assert_eq!(info.id, "gemma3");
+        assert_eq!(info.context_length, Some(8192));
+        assert_eq!(info.capabilities, vec!["completion", "vision"]);
+        assert!(!info.params.contains_key("stop"));
+    }
+}
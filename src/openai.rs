@@ -0,0 +1,218 @@
+//! The backend for OpenAI-compatible servers (llama.cpp's
+//! `llama-server`, vLLM, LM Studio, and so on), which lui talks to
+//! directly instead of through Open WebUI.
+//!
+//! These servers have no file store, so RAG is not available, and they
+//! return no `sources`.  Everything else (streaming, images, usage
+//! counts) works as it does with Open WebUI.
+
+use serde_json::Value;
+use ureq::BodyReader;
+
+use crate::backend::Backend;
+use crate::context::Context;
+use crate::prompt::Prompt;
+use crate::schema;
+use crate::server::{
+    FileRef, OutputReader, Request, Server, StreamOptions,
+    assemble_messages, read_output,
+};
+
+pub struct OpenAi<'a> {
+    server: &'a Server,
+}
+
+impl<'a> OpenAi<'a> {
+    pub fn new(server: &'a Server) -> Self {
+        Self { server }
+    }
+
+    /// Builds the URL of an endpoint under the API root.
+    ///
+    /// A `base-url` is expected to point at the API root already
+    /// (e.g., `http://localhost:8080/v1`).  With `host` and `port`, the
+    /// root is `/v1`.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the server's address is
+    /// misconfigured (see [`Server::url`]).
+    fn url(&self, path: &str) -> Result<String, String> {
+        if self.server.base_url.is_some() {
+            self.server.url(path)
+        } else {
+            self.server.url(&format!("/v1{path}"))
+        }
+    }
+}
+
+impl Backend for OpenAi<'_> {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    /// Sends a prompt and a context to `/chat/completions`.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if there are RAG files to attach,
+    /// no model is specified, the request fails, or the response is
+    /// malformed.
+    fn send(
+        &self,
+        prompt: &Prompt,
+        context: &Context,
+        files: &[FileRef],
+        stream: bool,
+    ) -> Result<OutputReader<BodyReader<'static>>, String> {
+        if !files.is_empty() {
+            return Err(crate::backend::no_file_store(self.name()));
+        }
+
+        let request = Request {
+            model: prompt
+                .model
+                .as_deref()
+                .ok_or_else(|| "no model specified".to_string())?
+                .to_string(),
+            messages: assemble_messages(context, prompt),
+            stream,
+            params: prompt.params.clone(),
+            response_format: prompt
+                .schema
+                .as_ref()
+                .map(schema::response_format),
+            stream_options: stream.then_some(StreamOptions {
+                include_usage: true,
+            }),
+            files: Vec::new(),
+        };
+
+        let response = self
+            .server
+            .agent()?
+            .post(&self.url("/chat/completions")?)
+            .header("Authorization", &self.server.bearer()?)
+            .send_json(&request)
+            .map_err(|x| format!("{x}"))?;
+
+        read_output(response, stream)
+    }
+
+    /// Lists the models from `/models`.
+    ///
+    /// Servers report the context window in different fields, if at all.
+    /// vLLM's `max_model_len` and llama.cpp's `meta.n_ctx_train` are
+    /// copied to where Open WebUI keeps it, `info.params.num_ctx`, so
+    /// that [`crate::server::model_info`] and the context check find it.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the request fails or the response
+    /// has no `data` array.
+    fn list_models(&self) -> Result<Vec<Value>, String> {
+        let value: Value = self
+            .server
+            .agent()?
+            .get(&self.url("/models")?)
+            .header("Authorization", &self.server.bearer()?)
+            .call()
+            .map_err(|x| format!("{x}"))?
+            .into_body()
+            .read_json()
+            .map_err(|x| format!("{x}"))?;
+
+        let models = value["data"]
+            .as_array()
+            .ok_or_else(|| "malformed model list".to_string())?;
+
+        Ok(models.iter().cloned().map(normalize_model).collect())
+    }
+}
+
+/// Copies the model's context window, if the entry reports one, to
+/// `info.params.num_ctx`.
+fn normalize_model(mut model: Value) -> Value {
+    let num_ctx = model["max_model_len"]
+        .as_u64()
+        .or_else(|| model["meta"]["n_ctx_train"].as_u64());
+
+    if let Some(num_ctx) = num_ctx
+        && model["info"]["params"]["num_ctx"].is_null()
+        && let Some(object) = model.as_object_mut()
+    {
+        object.insert(
+            "info".to_string(),
+            serde_json::json!({ "params": { "num_ctx": num_ctx } }),
+        );
+    }
+
+    model
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt::Params;
+    use serde_json::json;
+
+    #[test]
+    fn url_adds_v1_only_without_base_url() {
+        let mut server = Server::default();
+        server.host = Some("127.0.0.1".to_string());
+        server.port = Some(8080);
+
+        assert_eq!(
+            OpenAi::new(&server).url("/models").unwrap(),
+            "http://127.0.0.1:8080/v1/models"
+        );
+
+        let mut server = Server::default();
+        server.base_url = Some("http://gpu:8000/v1/".to_string());
+
+        assert_eq!(
+            OpenAi::new(&server).url("/chat/completions").unwrap(),
+            "http://gpu:8000/v1/chat/completions"
+        );
+    }
+
+    #[test]
+    fn normalize_model_finds_context_window() {
+        let vllm = normalize_model(json!({
+            "id": "qwen",
+            "max_model_len": 32768,
+        }));
+        assert_eq!(vllm["info"]["params"]["num_ctx"], 32768);
+
+        let llama_cpp = normalize_model(json!({
+            "id": "gemma",
+            "meta": { "n_ctx_train": 8192 },
+        }));
+        assert_eq!(llama_cpp["info"]["params"]["num_ctx"], 8192);
+
+        let unknown = normalize_model(json!({ "id": "x" }));
+        assert!(unknown["info"].is_null());
+    }
+
+    #[test]
+    fn request_asks_for_usage_when_streaming() {
+        let request = Request {
+            model: "m".to_string(),
+            messages: Vec::new(),
+            stream: true,
+            params: Params::new(),
+            response_format: None,
+            stream_options: Some(StreamOptions {
+                include_usage: true,
+            }),
+            files: Vec::new(),
+        };
+
+        let json = serde_json::to_string(&request).unwrap();
+
+        assert!(
+            json.contains(r#""stream_options":{"include_usage":true}"#),
+            "unexpected serialization: {json}"
+        );
+    }
+}
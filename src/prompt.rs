@@ -1,6 +1,15 @@
 use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
 
-use crate::server::{Message, MessageContent};
+use crate::server::{Message, MessageContent, RESERVED_PARAMS};
+
+/// Generation parameters sent along with a request, e.g.,
+/// `temperature`, `top_p`, `seed`, `max_tokens`, `stop`, or `num_ctx`.
+/// Lui passes them through without checking values, and names only
+/// against the fields it sets itself, since which parameters a server
+/// accepts depends on the server.
+pub type Params = BTreeMap<String, Value>;
 
 #[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct Prompt {
@@ -9,6 +18,13 @@ pub struct Prompt {
     pub system: Option<String>,
     pub question: String,
     pub model: Option<String>,
+
+    #[serde(default)]
+    pub params: Params,
+
+    /// A JSON schema that the answer must match (`--schema`).
+    #[serde(skip)]
+    pub schema: Option<Value>,
 }
 
 impl Prompt {
@@ -38,18 +54,52 @@ impl Prompt {
             result.extend_from_slice(xs);
         }
 
-        result.push(Message {
-            role: "user".to_string(),
-            content: MessageContent::Text(format!(
-                "#Prompt\n\n{}",
-                self.question
-            )),
-        });
+        result.push(question_message(&self.question));
 
         result
     }
 }
 
+/// The user message that asks `question`, as [`Prompt::as_messages`]
+/// sends it.
+pub fn question_message(question: &str) -> Message {
+    Message {
+        role: "user".to_string(),
+        content: MessageContent::Text(format!("#Prompt\n\n{question}")),
+    }
+}
+
+/// Parses a `key=value` parameter from the command line.
+///
+/// The value is read as JSON, so that `temperature=0` sends a number
+/// and `stop=["\n"]` an array.  A value that is not valid JSON is sent
+/// as a string, so `stop=END` works without quoting.
+///
+/// # Errors
+///
+/// This function returns an error if there is no `=`, the key is
+/// empty, or it names a field that lui sets itself (e.g., `model`,
+/// which is set with `-m`, or `stream`, with `-S`).
+pub fn parse_param(raw: &str) -> Result<(String, Value), String> {
+    let (key, value) = raw
+        .split_once('=')
+        .filter(|(key, _)| !key.trim().is_empty())
+        .ok_or_else(|| {
+            format!("parameter {raw:?} is not of form 'key=value'")
+        })?;
+
+    let key = key.trim();
+
+    if RESERVED_PARAMS.contains(&key) {
+        return Err(format!("parameter {key:?} is set by lui itself"));
+    }
+
+    let value = serde_json::from_str(value)
+        .unwrap_or_else(|_| Value::String(value.to_string()));
+
+    Ok((key.to_string(), value))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -63,6 +113,8 @@ mod tests {
                 system: None,
                 question: "foo bar".to_string(),
                 model: None,
+                params: Params::new(),
+                schema: None,
             }
             .as_messages(),
             vec![Message {
@@ -83,6 +135,8 @@ mod tests {
                 system: Some("baz".to_string()),
                 question: "foo bar".to_string(),
                 model: None,
+                params: Params::new(),
+                schema: None,
             }
             .as_messages(),
             vec![
@@ -122,6 +176,8 @@ mod tests {
                 system: None,
                 question: "foo bar".to_string(),
                 model: None,
+                params: Params::new(),
+                schema: None,
             }
             .as_messages(),
             vec![
@@ -163,6 +219,8 @@ mod tests {
                 system: Some("baz".to_string()),
                 question: "foo bar".to_string(),
                 model: None,
+                params: Params::new(),
+                schema: None,
             }
             .as_messages(),
             vec![
@@ -185,4 +243,34 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn parse_param_reads_json_or_string() {
+        use serde_json::json;
+
+        assert_eq!(
+            parse_param("temperature=0").unwrap(),
+            ("temperature".to_string(), json!(0))
+        );
+        assert_eq!(
+            parse_param("top_p=0.9").unwrap(),
+            ("top_p".to_string(), json!(0.9))
+        );
+        assert_eq!(
+            parse_param(r#"stop=["\n\n", "END"]"#).unwrap(),
+            ("stop".to_string(), json!(["\n\n", "END"]))
+        );
+        assert_eq!(
+            parse_param("stop=END").unwrap(),
+            ("stop".to_string(), json!("END"))
+        );
+        assert_eq!(
+            parse_param("note=a=b").unwrap(),
+            ("note".to_string(), json!("a=b"))
+        );
+        assert!(parse_param("temperature").is_err());
+        assert!(parse_param("=0").is_err());
+        assert!(parse_param("stream=true").is_err());
+        assert!(parse_param(" model =gemma").is_err());
+    }
 }
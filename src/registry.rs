@@ -0,0 +1,300 @@
+//! Remembers RAG uploads by the SHA-256 hash of their content, so that
+//! a file asked about again and again is uploaded and indexed once
+//! instead of on every run (`--reuse-uploads`).  An upload can also be
+//! given a short name (`--reuse-as`), which `-r @<name>` refers to.
+//!
+//! Like the [journal](crate::journal), the registry keeps one file per
+//! entry (`<registry_dir>/<hash>`), so concurrent `lui` invocations
+//! never clobber each other's records.  Each file holds the entry as
+//! JSON.
+//!
+//! Registered uploads are not deleted after the query.  An entry whose
+//! upload no longer exists on the server (e.g., after `--prune-all`) is
+//! stale: it is replaced by a new upload when the file is used again,
+//! and dropped by `--prune`.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::journal;
+use crate::server::is_safe_id;
+
+/// Returns the directory in which registry entries are stored for the
+/// server profile `server`: the sibling `registry` of the
+/// [`journal::pending_dir`].
+///
+/// Returns `None` if the user's home directory cannot be determined.
+pub fn registry_dir(server: Option<&str>) -> Option<PathBuf> {
+    Some(journal::pending_dir(server)?.with_file_name("registry"))
+}
+
+/// A registered upload.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// The SHA-256 hash of the file's content, in hex.
+    #[serde(skip)]
+    pub hash: String,
+
+    /// The server-assigned ID of the upload.
+    pub id: String,
+
+    /// The path the file was uploaded from.
+    pub path: String,
+
+    /// The name that `-r @<name>` refers to the upload by.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Returns the SHA-256 hash of the content of the file at `path`, in
+/// hex.
+///
+/// # Errors
+///
+/// This function returns an error if the file cannot be read.
+pub fn hash_file(path: &Path) -> Result<String, String> {
+    let error =
+        |x: std::io::Error| format!("{}: {x}", path.to_string_lossy());
+
+    let mut file = std::fs::File::open(path).map_err(error)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        match file.read(&mut buffer).map_err(error)? {
+            0 => break,
+            n => hasher.update(&buffer[..n]),
+        }
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|x| format!("{x:02x}"))
+        .collect())
+}
+
+/// Returns the entry for `hash`, if there is one.
+///
+/// # Errors
+///
+/// This function returns an error if the entry exists but cannot be
+/// read or parsed.
+pub fn get(dir: &Path, hash: &str) -> Result<Option<Entry>, String> {
+    let path = dir.join(hash);
+
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(x) if x.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(None);
+        }
+        Err(x) => {
+            return Err(format!("{}: {x}", path.to_string_lossy()));
+        }
+    };
+
+    let mut entry: Entry = serde_json::from_str(&text)
+        .map_err(|x| format!("{}: {x}", path.to_string_lossy()))?;
+
+    if !is_safe_id(&entry.id) {
+        return Err(format!(
+            "{}: unsafe file ID {:?}",
+            path.to_string_lossy(),
+            entry.id
+        ));
+    }
+
+    entry.hash = hash.to_string();
+
+    Ok(Some(entry))
+}
+
+/// Writes `entry`, replacing any earlier entry for the same hash, and
+/// creating `dir` if necessary.
+///
+/// If the entry has a name, the name is taken away from any other entry
+/// that holds it, so that `-r @<name>` refers to the newest upload.
+///
+/// # Errors
+///
+/// This function returns an error if the directory cannot be created,
+/// the entry cannot be written, or another entry cannot be read or
+/// rewritten.
+pub fn put(dir: &Path, entry: &Entry) -> Result<(), String> {
+    std::fs::create_dir_all(dir)
+        .map_err(|x| format!("{}: {x}", dir.to_string_lossy()))?;
+
+    if entry.name.is_some() {
+        for mut other in load(dir)? {
+            if other.hash != entry.hash && other.name == entry.name {
+                other.name = None;
+                write(dir, &other)?;
+            }
+        }
+    }
+
+    write(dir, entry)
+}
+
+/// Removes the entry for `hash`.  A missing entry is not an error.
+///
+/// # Errors
+///
+/// This function returns an error if the entry exists but cannot be
+/// removed.
+pub fn remove(dir: &Path, hash: &str) -> Result<(), String> {
+    journal::remove(dir, hash)
+}
+
+/// Returns every entry.  A missing directory yields an empty list.
+///
+/// # Errors
+///
+/// This function returns an error if the directory exists but cannot be
+/// read, or an entry cannot be read or parsed.
+pub fn load(dir: &Path) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+
+    for hash in journal::load(dir)? {
+        // Skip the temporary files of concurrent writes.
+        if hash.starts_with('.') {
+            continue;
+        }
+
+        if let Some(entry) = get(dir, &hash)? {
+            entries.push(entry);
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Returns the entry named `name`.
+///
+/// # Errors
+///
+/// This function returns an error if the registry cannot be read, or no
+/// entry is named `name`.
+pub fn find(dir: &Path, name: &str) -> Result<Entry, String> {
+    load(dir)?
+        .into_iter()
+        .find(|x| x.name.as_deref() == Some(name))
+        .ok_or_else(|| format!("@{name}: no upload has this name"))
+}
+
+/// Checks that `name` can be used as `-r @<name>`.
+///
+/// # Errors
+///
+/// This function returns an error if `name` is empty or contains
+/// anything other than ASCII letters, digits, `-`, `_`, and `.`.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty()
+        || !name.chars().all(|c| {
+            c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')
+        })
+    {
+        return Err(format!(
+            "invalid upload name '{name}': use letters, digits, '-', \
+             '_', and '.'"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Writes `entry` to a temporary file first and then renames it, so a
+/// concurrent reader never sees a partial entry.
+fn write(dir: &Path, entry: &Entry) -> Result<(), String> {
+    let path = dir.join(&entry.hash);
+    let tmp =
+        dir.join(format!(".{}.{}", entry.hash, std::process::id()));
+
+    let text =
+        serde_json::to_string(entry).map_err(|x| x.to_string())?;
+
+    std::fs::write(&tmp, text)
+        .and_then(|()| std::fs::rename(&tmp, &path))
+        .map_err(|x| format!("{}: {x}", path.to_string_lossy()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn temporary_dir(tag: &str) -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+
+        std::env::temp_dir().join(format!(
+            "lui-registry-{tag}-{}-{nanos}",
+            std::process::id()
+        ))
+    }
+
+    fn entry(hash: &str, id: &str, name: Option<&str>) -> Entry {
+        Entry {
+            hash: hash.to_string(),
+            id: id.to_string(),
+            path: format!("{id}.pdf"),
+            name: name.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn hash_file_is_sha256_of_the_content() {
+        let dir = temporary_dir("hash");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("abc.txt");
+        std::fs::write(&path, "abc").unwrap();
+
+        assert_eq!(
+            hash_file(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223\
+             b00361a396177a9cb410ff61f20015ad"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn entries_are_stored_found_by_name_and_removed() {
+        let dir = temporary_dir("entries");
+
+        assert!(load(&dir).unwrap().is_empty());
+        assert_eq!(get(&dir, "h1").unwrap(), None);
+
+        put(&dir, &entry("h1", "id-1", Some("handbook"))).unwrap();
+        put(&dir, &entry("h2", "id-2", None)).unwrap();
+
+        assert_eq!(get(&dir, "h2").unwrap().unwrap().id, "id-2");
+        assert_eq!(find(&dir, "handbook").unwrap().id, "id-1");
+        assert!(find(&dir, "manual").is_err());
+
+        // A new upload under the same name takes the name over.
+        put(&dir, &entry("h3", "id-3", Some("handbook"))).unwrap();
+
+        assert_eq!(find(&dir, "handbook").unwrap().id, "id-3");
+        assert_eq!(get(&dir, "h1").unwrap().unwrap().name, None);
+
+        remove(&dir, "h1").unwrap();
+        remove(&dir, "h1").unwrap();
+
+        assert_eq!(load(&dir).unwrap().len(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn validate_name_rejects_paths() {
+        assert!(validate_name("handbook-2025.v2").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("../x").is_err());
+        assert!(validate_name("a b").is_err());
+    }
+}
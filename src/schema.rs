@@ -0,0 +1,268 @@
+//! Structured output: asking the model for JSON that matches a schema
+//! (`--schema`), and checking the answer locally.
+//!
+//! The schema is sent with the request (see [`response_format`]), but
+//! servers and models honor it unevenly: some constrain decoding to it,
+//! some only take it as a hint, and some ignore it.  So lui validates
+//! the answer itself and, if it doesn't match, asks the model again
+//! with the validation errors (see [`retry_prompt`]).
+
+use serde_json::Value;
+use std::path::Path;
+
+use crate::prompt::Prompt;
+use crate::server::{Message, MessageContent};
+
+/// How many validation errors to report to the user and the model.
+/// More are rarely useful, since later errors often follow from the
+/// first ones.
+const MAX_ERRORS: usize = 5;
+
+/// A JSON schema, compiled for validation.
+pub struct Schema {
+    value: Value,
+    validator: jsonschema::Validator,
+}
+
+impl Schema {
+    /// Reads and compiles the JSON schema at `path`.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the file cannot be read, is not
+    /// JSON, or is not a valid JSON schema.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|x| format!("{}: {x}", path.to_string_lossy()))?;
+
+        let value: Value = serde_json::from_str(&content)
+            .map_err(|x| format!("{}: {x}", path.to_string_lossy()))?;
+
+        Self::new(value)
+            .map_err(|x| format!("{}: {x}", path.to_string_lossy()))
+    }
+
+    /// Compiles `value` as a JSON schema.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if `value` is not a valid JSON
+    /// schema.
+    pub fn new(value: Value) -> Result<Self, String> {
+        let validator = jsonschema::validator_for(&value)
+            .map_err(|x| format!("invalid JSON schema: {x}"))?;
+
+        Ok(Self { value, validator })
+    }
+
+    /// The schema as it was read.
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    /// Parses the model's answer as JSON and validates it against the
+    /// schema.  Returns the parsed value.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error describing why the answer is not
+    /// JSON or which parts of it do not match the schema.
+    pub fn validate(&self, message: &str) -> Result<Value, String> {
+        let value: Value = serde_json::from_str(strip_code_fence(
+            message,
+        ))
+        .map_err(|x| format!("the response is not valid JSON: {x}"))?;
+
+        let errors: Vec<String> = self
+            .validator
+            .iter_errors(&value)
+            .map(|error| {
+                let path = error.instance_path().to_string();
+
+                if path.is_empty() {
+                    error.to_string()
+                } else {
+                    format!("at {path}: {error}")
+                }
+            })
+            .collect();
+
+        if errors.is_empty() {
+            return Ok(value);
+        }
+
+        let mut summary = errors
+            .iter()
+            .take(MAX_ERRORS)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        if errors.len() > MAX_ERRORS {
+            summary.push_str(&format!(
+                "; and {} more",
+                errors.len() - MAX_ERRORS
+            ));
+        }
+
+        Err(format!(
+            "the response does not match the schema: {summary}"
+        ))
+    }
+}
+
+/// The `response_format` field of an OpenAI-style chat request that
+/// asks for JSON matching `schema`.
+pub fn response_format(schema: &Value) -> Value {
+    serde_json::json!({
+        "type": "json_schema",
+        "json_schema": {
+            "name": "response",
+            "schema": schema,
+        },
+    })
+}
+
+/// Builds the prompt for another attempt after `response` failed
+/// validation with `error`.
+///
+/// The failed exchange becomes part of the history, so the model sees
+/// its previous answer and what was wrong with it, followed by a
+/// request to answer again.
+pub fn retry_prompt(
+    prompt: &Prompt,
+    response: &str,
+    error: &str,
+) -> Prompt {
+    let mut history = prompt.history.clone().unwrap_or_default();
+
+    // The question as `Prompt::as_messages` would have sent it, which
+    // is always the last message.
+    if let Some(question) = prompt.as_messages().pop() {
+        history.push(question);
+    }
+
+    history.push(Message {
+        role: "assistant".to_string(),
+        content: MessageContent::Text(response.to_string()),
+    });
+
+    Prompt {
+        history: Some(history),
+        question: format!(
+            "Your response was rejected because {error}.  Answer again \
+             with only a JSON value that matches the schema, without \
+             any other text."
+        ),
+        ..prompt.clone()
+    }
+}
+
+/// Removes a Markdown code fence around `message`, which some models
+/// add even when asked for bare JSON.
+fn strip_code_fence(message: &str) -> &str {
+    let trimmed = message.trim();
+
+    let Some(rest) = trimmed.strip_prefix("```") else {
+        return trimmed;
+    };
+
+    // Skip the info string (e.g., `json`) on the opening line.
+    let body = rest.split_once('\n').map_or("", |(_, body)| body);
+
+    body.trim_end()
+        .strip_suffix("```")
+        .map_or(trimmed, str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt::Params;
+    use serde_json::json;
+
+    fn person_schema() -> Schema {
+        Schema::new(json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "age": { "type": "integer", "minimum": 0 },
+            },
+            "required": ["name", "age"],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn validate_accepts_matching_json() {
+        assert_eq!(
+            person_schema()
+                .validate(r#"{"name": "Ada", "age": 36}"#)
+                .unwrap(),
+            json!({ "name": "Ada", "age": 36 })
+        );
+    }
+
+    #[test]
+    fn validate_reports_where_it_fails() {
+        let err = person_schema()
+            .validate(r#"{"name": "Ada", "age": -1}"#)
+            .unwrap_err();
+        assert!(err.contains("/age"), "unexpected error: {err}");
+
+        let err =
+            person_schema().validate(r#"{"name": 1}"#).unwrap_err();
+        assert!(err.contains("\"age\""), "unexpected error: {err}");
+        assert!(err.contains("/name"), "unexpected error: {err}");
+
+        let err = person_schema().validate("Ada is 36.").unwrap_err();
+        assert!(
+            err.contains("not valid JSON"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    fn validate_strips_code_fences() {
+        assert!(
+            person_schema()
+                .validate(
+                    "```json\n{\"name\": \"Ada\", \"age\": 36}\n```\n"
+                )
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn new_rejects_invalid_schema() {
+        assert!(Schema::new(json!({ "type": "strnig" })).is_err());
+    }
+
+    #[test]
+    fn retry_prompt_appends_failed_exchange() {
+        let prompt = Prompt {
+            label: String::new(),
+            history: None,
+            system: Some("Be terse.".to_string()),
+            question: "Who?".to_string(),
+            model: Some("m".to_string()),
+            params: Params::new(),
+            schema: None,
+        };
+
+        let retry = retry_prompt(&prompt, "Ada", "it is not JSON");
+        let messages = retry.as_messages();
+
+        let roles: Vec<&str> =
+            messages.iter().map(|x| x.role.as_str()).collect();
+        assert_eq!(roles, vec!["system", "user", "assistant", "user"]);
+        assert_eq!(
+            messages[1].content,
+            MessageContent::Text("#Prompt\n\nWho?".to_string())
+        );
+        assert_eq!(
+            messages[2].content,
+            MessageContent::Text("Ada".to_string())
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Reading the Open WebUI API key from the places the `[server]` table
+//! can point to: the config itself, a command, an environment variable,
+//! or a separate secrets file.
+//!
+//! The key is wrapped in [`Secret`] as soon as it is read so that a
+//! stray `{:?}` in a log line prints a placeholder instead of the key.
+
+use serde::Deserialize;
+use std::path::Path;
+
+/// A string that must not show up in logs.  Its `Debug` implementation
+/// prints a placeholder rather than the value.
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Self(value)
+    }
+
+    /// Returns the secret value.  Only call this where the value is
+    /// actually sent somewhere, never to format a log message.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(***)")
+    }
+}
+
+/// Runs `command` with `sh -c` and returns the first line of its
+/// standard output.  This matches the convention of password managers
+/// such as `pass`, which print the password on the first line and
+/// metadata below it.  Standard error is inherited so that the user
+/// sees prompts (e.g., from `gpg`, whose pinentry reads from the
+/// terminal).  Standard input is not, so that the command cannot eat
+/// what is piped to lui (e.g., for `-i -`).
+///
+/// # Errors
+///
+/// This function returns an error if the command cannot be started,
+/// exits with a non-zero status, or prints nothing but whitespace on
+/// its first line.
+pub fn from_command(command: &str) -> Result<Secret, String> {
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .map_err(|x| format!("api-key-command: {x}"))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "api-key-command: exited with {}",
+            output.status
+        ));
+    }
+
+    let stdout = String::from_utf8(output.stdout).map_err(|_| {
+        "api-key-command: output is not valid UTF-8".to_string()
+    })?;
+
+    non_empty(
+        stdout.lines().next().unwrap_or("").trim(),
+        "api-key-command",
+    )
+}
+
+/// Reads the key from the environment variable `name`.
+///
+/// # Errors
+///
+/// This function returns an error if the variable is unset, not valid
+/// Unicode, or empty.
+pub fn from_env(name: &str) -> Result<Secret, String> {
+    let value = std::env::var(name)
+        .map_err(|x| format!("api-key-env: ${name}: {x}"))?;
+
+    non_empty(value.trim(), &format!("api-key-env: ${name}"))
+}
+
+/// Reads the key from the file at `path`, ignoring surrounding
+/// whitespace.
+///
+/// The file must not be readable by the group or by others, for the
+/// same reason that `ssh` refuses loose private key files.
+///
+/// # Errors
+///
+/// This function returns an error if the file cannot be read, is
+/// group- or world-readable, or holds nothing but whitespace.
+pub fn from_file(path: &Path) -> Result<Secret, String> {
+    check_private(path)?;
+
+    let content = std::fs::read_to_string(path)
+        .map_err(|x| format!("{}: {x}", path.to_string_lossy()))?;
+
+    non_empty(content.trim(), &path.to_string_lossy())
+}
+
+/// Returns an error if the file at `path` is readable by its group or
+/// by others.  Always succeeds on platforms without Unix permissions.
+///
+/// # Errors
+///
+/// This function returns an error if the file's metadata cannot be read
+/// or its permissions are too open.
+pub fn check_private(path: &Path) -> Result<(), String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(path)
+            .map_err(|x| format!("{}: {x}", path.to_string_lossy()))?
+            .permissions()
+            .mode();
+
+        if mode & 0o077 != 0 {
+            return Err(format!(
+                "{}: permissions {:03o} are too open; the file holds \
+                 the API key, so run `chmod 600` on it",
+                path.to_string_lossy(),
+                mode & 0o777
+            ));
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = path;
+
+    Ok(())
+}
+
+fn non_empty(value: &str, source: &str) -> Result<Secret, String> {
+    if value.is_empty() {
+        Err(format!("{source}: API key is empty"))
+    } else {
+        Ok(Secret::new(value.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_debug_hides_the_value() {
+        let secret = Secret::new("sk-1234".to_string());
+
+        assert_eq!(format!("{secret:?}"), "Secret(***)");
+        assert_eq!(secret.expose(), "sk-1234");
+    }
+
+    #[test]
+    fn from_command_takes_first_line() {
+        assert_eq!(
+            from_command("printf 'sk-1234\\nlogin: me\\n'")
+                .unwrap()
+                .expose(),
+            "sk-1234"
+        );
+
+        assert!(from_command("exit 3").is_err());
+        assert!(from_command("echo").is_err());
+
+        // The command gets no input, rather than lui's.
+        assert_eq!(
+            from_command("cat; echo sk-5678").unwrap().expose(),
+            "sk-5678"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn from_file_refuses_loose_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir()
+            .join(format!("lui-secret-{}", std::process::id()));
+        std::fs::write(&path, "sk-1234\n").unwrap();
+
+        std::fs::set_permissions(
+            &path,
+            std::fs::Permissions::from_mode(0o644),
+        )
+        .unwrap();
+        let err = from_file(&path).unwrap_err();
+        assert!(err.contains("too open"), "unexpected error: {err}");
+
+        std::fs::set_permissions(
+            &path,
+            std::fs::Permissions::from_mode(0o600),
+        )
+        .unwrap();
+        assert_eq!(from_file(&path).unwrap().expose(), "sk-1234");
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
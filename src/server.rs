@@ -53,25 +53,310 @@
 use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 use std::borrow::Cow;
-use std::io::{BufRead, BufReader, Read};
-use std::path::Path;
+use std::io::{BufRead, BufReader, Chain, Cursor, Read, Take};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::time::{SystemTime, UNIX_EPOCH};
-use ureq::BodyReader;
-
-use crate::context::Context;
-use crate::prompt::Prompt;
+use ureq::tls::{
+    Certificate, ClientCert, PemItem, PrivateKey, RootCerts, TlsConfig,
+};
+use ureq::{Agent, BodyReader};
+
+use crate::backend::{Backend, BackendKind, Indexing};
+use crate::context::{self, Context, RagFile};
+use crate::ollama::{ChunkIter, Ollama};
+use crate::openai::OpenAi;
+use crate::prompt::{Params, Prompt};
+use crate::schema;
+use crate::secret::{self, Secret};
+
+/// How many idle connections the agent keeps open, enough for the most
+/// parallel uploads that `--upload-jobs` allows.
+const MAX_IDLE_CONNECTIONS: usize = 32;
 
 /// Access details for open-webui.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct Server {
-    pub host: String,
-    pub port: u16,
+    /// The profile name from `[servers.<name>]`, or `None` for the
+    /// unnamed `[server]` table.  Set by [`Config::load`].
+    ///
+    /// [`Config::load`]: crate::config::Config::load
+    #[serde(skip)]
+    pub name: Option<String>,
+
+    pub host: Option<String>,
+    pub port: Option<u16>,
 
+    /// Full URL of the Open WebUI instance, including any path prefix
+    /// (e.g., `https://tools.corp/webui/`).  An alternative to `host`
+    /// and `port`.
+    #[serde(rename = "base-url")]
+    pub base_url: Option<String>,
+
+    /// The API key, stored inline.  Exactly one of `api-key`,
+    /// `api-key-command`, `api-key-env`, and `api-key-file` must be set.
     #[serde(rename = "api-key")]
-    pub api_key: String,
+    pub api_key: Option<Secret>,
+
+    /// Shell command that prints the API key on its first line (e.g.,
+    /// `pass show webui`).
+    #[serde(rename = "api-key-command")]
+    pub api_key_command: Option<String>,
+
+    /// Name of the environment variable that holds the API key.
+    #[serde(rename = "api-key-env")]
+    pub api_key_env: Option<String>,
+
+    /// File that holds the API key.  It must not be readable by the
+    /// group or by others.
+    #[serde(rename = "api-key-file")]
+    pub api_key_file: Option<PathBuf>,
+
+    /// The API key once it has been read, so that `api-key-command` runs
+    /// at most once per invocation.
+    #[serde(skip)]
+    resolved_api_key: OnceLock<Secret>,
+
+    /// The HTTP agent once it has been built, so that every request
+    /// (and every thread) shares its pool of keep-alive connections.
+    #[serde(skip)]
+    shared_agent: OnceLock<Agent>,
+
+    /// Connect over HTTPS instead of plain HTTP.
+    #[serde(default)]
+    pub tls: bool,
+
+    /// PEM file with the root certificates to trust instead of the
+    /// bundled Mozilla roots (e.g., a corporate CA).
+    #[serde(rename = "ca-bundle")]
+    pub ca_bundle: Option<PathBuf>,
+
+    /// PEM file with the client certificate chain to present to the
+    /// server.  May also hold the private key.
+    #[serde(rename = "client-cert")]
+    pub client_cert: Option<PathBuf>,
+
+    /// PEM file with the private key for `client-cert`, if it is not
+    /// stored in the same file.
+    #[serde(rename = "client-key")]
+    pub client_key: Option<PathBuf>,
+
+    /// The API to speak: `open-webui` (the default), `openai` for an
+    /// OpenAI-compatible server such as llama.cpp's or vLLM's, or
+    /// `ollama` for Ollama's native API.
+    #[serde(default)]
+    pub backend: BackendKind,
+
+    /// Save every exchange to Open WebUI's chat history, as a new chat,
+    /// as if `--save-chat` were given.
+    #[serde(rename = "save-chat", default)]
+    pub save_chat: bool,
+
+    /// Keep RAG uploads and reuse them in later runs, as if
+    /// `--reuse-uploads` were given.
+    #[serde(rename = "reuse-uploads", default)]
+    pub reuse_uploads: bool,
+
+    /// Let Open WebUI index RAG files in the background, as if
+    /// `--index-in-background` were given.
+    #[serde(rename = "index-in-background", default)]
+    pub index_in_background: bool,
+
+    /// How many seconds to wait for background indexing, as if
+    /// `--index-timeout` were given.
+    #[serde(rename = "index-timeout")]
+    pub index_timeout: Option<u64>,
+
+    /// The size in MB above which a RAG file is not uploaded, as if
+    /// `--max-upload-size` were given.  Matches Open WebUI's
+    /// `FILE_MAX_SIZE`, which is in MB as well.
+    #[serde(rename = "max-upload-size")]
+    pub max_upload_size: Option<u64>,
 }
 
 impl Server {
+    /// Builds a full request URL from a path beginning with `/`.
+    ///
+    /// The path is appended to `base-url` (keeping its path prefix) if
+    /// that is set, in which case its scheme overrides `tls`.
+    /// Otherwise, it is appended to `host` and `port`.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if
+    ///
+    /// - both `base-url` and `host`/`port` are set,
+    /// - neither `base-url` nor both of `host` and `port` are set, or
+    /// - `base-url` is not an `http://` or `https://` URL.
+    pub fn url(&self, path: &str) -> Result<String, String> {
+        let base = match (&self.base_url, &self.host, self.port) {
+            (Some(_), Some(_), _) | (Some(_), _, Some(_)) => {
+                return Err("server: set either base-url or host and \
+                            port, not both"
+                    .to_string());
+            }
+            (Some(base_url), None, None) => {
+                if !base_url.starts_with("http://")
+                    && !base_url.starts_with("https://")
+                {
+                    return Err(format!(
+                        "server: base-url {base_url:?} must start with \
+                         http:// or https://"
+                    ));
+                }
+
+                base_url.trim_end_matches('/').to_string()
+            }
+            (None, Some(host), Some(port)) => {
+                let scheme = if self.tls { "https" } else { "http" };
+
+                // An IPv6 literal has to be bracketed so that its
+                // colons aren't taken for the port separator.
+                if host.contains(':') && !host.starts_with('[') {
+                    format!("{scheme}://[{host}]:{port}")
+                } else {
+                    format!("{scheme}://{host}:{port}")
+                }
+            }
+            (None, _, _) => {
+                return Err("server: set either base-url or host and \
+                            port"
+                    .to_string());
+            }
+        };
+
+        Ok(format!("{base}{path}"))
+    }
+
+    /// Returns the HTTP agent that every request to open-webui goes
+    /// through, configured with the custom CA bundle and client
+    /// certificate, if any.  The agent is built on first use and then
+    /// shared, so connections are kept alive between requests.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the CA bundle, the client
+    /// certificate, or the client key cannot be read or contains no
+    /// usable PEM item.
+    pub fn agent(&self) -> Result<&Agent, String> {
+        if let Some(agent) = self.shared_agent.get() {
+            return Ok(agent);
+        }
+
+        let agent = self.build_agent()?;
+
+        // Another thread may have built an agent in the meantime, in
+        // which case that one is used and this one dropped.
+        Ok(self.shared_agent.get_or_init(|| agent))
+    }
+
+    fn build_agent(&self) -> Result<Agent, String> {
+        if !self.tls
+            && (self.ca_bundle.is_some() || self.client_cert.is_some())
+        {
+            log::warn!(
+                "ca-bundle and client-cert are ignored unless tls = true"
+            );
+        }
+
+        let mut tls_config = TlsConfig::builder();
+
+        if let Some(ref path) = self.ca_bundle {
+            let certs = read_pem_certs(path)?;
+
+            tls_config = tls_config
+                .root_certs(RootCerts::new_with_certs(&certs));
+        }
+
+        if let Some(ref path) = self.client_cert {
+            let certs = read_pem_certs(path)?;
+            let key = read_pem_key(
+                self.client_key.as_deref().unwrap_or(path),
+            )?;
+
+            tls_config = tls_config.client_cert(Some(
+                ClientCert::new_with_certs(&certs, key),
+            ));
+        }
+
+        let config = Agent::config_builder()
+            .tls_config(tls_config.build())
+            .max_idle_connections_per_host(MAX_IDLE_CONNECTIONS)
+            .build();
+
+        Ok(Agent::new_with_config(config))
+    }
+
+    /// Builds the `Authorization` header value.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the API key cannot be read (see
+    /// [`Server::api_key`]).
+    pub fn bearer(&self) -> Result<String, String> {
+        Ok(format!("Bearer {}", self.api_key()?.expose()))
+    }
+
+    /// Returns the API key from whichever source the configuration
+    /// names, reading it on first use.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if not exactly one source is
+    /// configured or if reading from it fails (see [`secret`]).
+    fn api_key(&self) -> Result<&Secret, String> {
+        if let Some(key) = self.resolved_api_key.get() {
+            return Ok(key);
+        }
+
+        let key = match (
+            &self.api_key,
+            &self.api_key_command,
+            &self.api_key_env,
+            &self.api_key_file,
+        ) {
+            (Some(key), None, None, None) => key.clone(),
+            (None, Some(command), None, None) => {
+                log::debug!("running api-key-command");
+                secret::from_command(command)?
+            }
+            (None, None, Some(name), None) => secret::from_env(name)?,
+            (None, None, None, Some(path)) => secret::from_file(path)?,
+            _ => {
+                return Err("server: set exactly one of api-key, \
+                            api-key-command, api-key-env, and \
+                            api-key-file"
+                    .to_string());
+            }
+        };
+
+        Ok(self.resolved_api_key.get_or_init(|| key))
+    }
+
+    /// Returns the backend that speaks the API selected by `backend`
+    /// in the configuration.
+    pub fn backend(&self) -> Box<dyn Backend + '_> {
+        match self.backend {
+            BackendKind::OpenWebUi => {
+                Box::new(OpenWebUi { server: self })
+            }
+            BackendKind::OpenAi => Box::new(OpenAi::new(self)),
+            BackendKind::Ollama => Box::new(Ollama::new(self)),
+        }
+    }
+}
+
+/// The backend for Open WebUI's own API: chat completions with RAG
+/// `files` and `sources`, and its file store.
+pub struct OpenWebUi<'a> {
+    server: &'a Server,
+}
+
+impl Backend for OpenWebUi<'_> {
+    fn name(&self) -> &'static str {
+        "open-webui"
+    }
+
     /// Send a prompt and a context to open-webui.
     ///
     /// Returns an `OutputReader::TokenIter` if `stream` is true and an
@@ -89,14 +374,14 @@ impl Server {
     ///   * contains a non-integer prompt token count, or
     ///   * contains a message or an approximate duration that is not
     ///     valid UTF-8.
-    pub fn send(
+    fn send(
         &self,
         prompt: &Prompt,
         context: &Context,
-        file_ids: &[String],
+        files: &[FileRef],
         stream: bool,
     ) -> Result<OutputReader<BodyReader<'static>>, String> {
-        let uri = self.url("/api/chat/completions");
+        let uri = self.server.url("/api/chat/completions")?;
 
         let request = Request {
             model: prompt
@@ -106,101 +391,66 @@ impl Server {
                 .to_string(),
             messages: assemble_messages(context, prompt),
             stream,
-            files: file_ids
-                .iter()
-                .map(|id| FileRef {
-                    kind: "file".to_string(),
-                    id: id.clone(),
-                })
-                .collect(),
+            params: prompt.params.clone(),
+            response_format: prompt
+                .schema
+                .as_ref()
+                .map(schema::response_format),
+            stream_options: None,
+            files: files.to_vec(),
         };
 
-        let response = ureq::post(&uri)
-            .header("Authorization", &self.bearer())
+        let response = self
+            .server
+            .agent()?
+            .post(&uri)
+            .header("Authorization", &self.server.bearer()?)
             .send_json(&request)
             .map_err(|x| format!("{x}"))?;
 
-        if stream {
-            let body_reader = response.into_body().into_reader();
-
-            Ok(OutputReader::Streamed(TokenIter {
-                reader: BufReader::new(body_reader),
-                sources: Vec::new(),
-            }))
-        } else {
-            let (output, sources) = get_complete_output(response)?;
-
-            Ok(OutputReader::Complete(OutputIter {
-                output: Some(output),
-                sources,
-            }))
-        }
-    }
-
-    /// Builds a full request URL from a path beginning with `/`.
-    fn url(&self, path: &str) -> String {
-        format!("http://{}:{}{}", self.host, self.port, path)
-    }
-
-    /// Builds the `Authorization` header value.
-    fn bearer(&self) -> String {
-        format!("Bearer {}", self.api_key)
+        read_output(response, stream)
     }
 
     /// Uploads `path` to open-webui's RAG file store and returns the ID
     /// the server assigned to it.
     ///
-    /// The request blocks until the file has been indexed
-    /// (`process_in_background=false`), so the returned ID can be
-    /// referenced in a chat request right away without polling.
+    /// Unless `background` is true, the request blocks until the file
+    /// has been indexed (`process_in_background=false`), so the
+    /// returned ID can be referenced in a chat request right away
+    /// without polling.
     ///
     /// ureq 3.1 has no multipart support, so the `multipart/form-data`
-    /// body is assembled by hand.
+    /// body is assembled by hand (see [`MultipartFile`]).  The file is
+    /// streamed from disk, so memory use does not grow with its size.
     ///
     /// # Errors
     ///
     /// This method returns an error if the file cannot be read, the HTTP
     /// request fails, or the response is not JSON containing a string,
     /// safe `id`.
-    pub fn upload_file(&self, path: &Path) -> Result<String, String> {
-        let uri =
-            self.url("/api/v1/files/?process_in_background=false");
-
-        // The filename is interpolated into a Content-Disposition
-        // header, so quotes and control characters (which a Unix
-        // filename may legally contain) must be removed to avoid
-        // producing a malformed or injectable multipart body.
-        let filename = sanitize_filename(path);
-        let content_type = content_type_for(path);
-
-        let boundary = multipart_boundary();
-        let mut body = Vec::new();
-        body.extend_from_slice(
-            format!(
-                "--{boundary}\r\n\
-                 Content-Disposition: form-data; \
-                 name=\"file\"; filename=\"{filename}\"\r\n\
-                 Content-Type: {content_type}\r\n\r\n"
-            )
-            .as_bytes(),
-        );
-        // Append the file's bytes straight into `body` so the file is
-        // held in memory only once.
-        std::fs::File::open(path)
-            .and_then(|mut file| file.read_to_end(&mut body))
-            .map_err(|x| format!("{}: {x}", path.to_string_lossy()))?;
-        body.extend_from_slice(
-            format!("\r\n--{boundary}--\r\n").as_bytes(),
-        );
-
-        let response = ureq::post(&uri)
-            .header("Authorization", &self.bearer())
-            .header(
-                "Content-Type",
-                &format!("multipart/form-data; boundary={boundary}"),
-            )
-            .send(body)
-            .map_err(|x| format!("{}: {x}", path.to_string_lossy()))?;
+    fn upload_file(
+        &self,
+        file: &RagFile,
+        background: bool,
+    ) -> Result<String, String> {
+        let label = &file.label;
+        let uri = self.server.url(&format!(
+            "/api/v1/files/?process_in_background={background}"
+        ))?;
+
+        let mut body = MultipartFile::open(file)?;
+
+        // Without a Content-Length, ureq would send a body read from a
+        // `Read` with chunked transfer encoding.
+        let response = self
+            .server
+            .agent()?
+            .post(&uri)
+            .header("Authorization", &self.server.bearer()?)
+            .header("Content-Type", &body.content_type)
+            .header("Content-Length", &body.len.to_string())
+            .send(ureq::SendBody::from_reader(&mut body))
+            .map_err(|x| format!("{label}: {x}"))?;
 
         let value: Value = response
             .into_body()
@@ -208,10 +458,7 @@ impl Server {
             .map_err(|x| format!("{x}"))?;
 
         let id = value["id"].as_str().ok_or_else(|| {
-            format!(
-                "{}: upload response has no file id",
-                path.to_string_lossy()
-            )
+            format!("{label}: upload response has no file id")
         })?;
 
         // The ID is later used as a path component (in the journal) and
@@ -219,14 +466,41 @@ impl Server {
         // a plain token before trusting it.
         if !is_safe_id(id) {
             return Err(format!(
-                "{}: server returned an unsafe file id {id:?}",
-                path.to_string_lossy()
+                "{label}: server returned an unsafe file id {id:?}"
             ));
         }
 
         Ok(id.to_string())
     }
 
+    /// Reads the status of a file uploaded in the background from
+    /// `/api/v1/files/{id}/process/status`.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if `id` is not a safe token, or the
+    /// HTTP request fails or returns something other than JSON.
+    fn indexing_status(&self, id: &str) -> Result<Indexing, String> {
+        if !is_safe_id(id) {
+            return Err(format!("unsafe file id {id:?}"));
+        }
+
+        let value: Value =
+            self.server
+                .agent()?
+                .get(&self.server.url(&format!(
+                    "/api/v1/files/{id}/process/status"
+                ))?)
+                .header("Authorization", &self.server.bearer()?)
+                .call()
+                .map_err(|x| format!("{id}: {x}"))?
+                .into_body()
+                .read_json()
+                .map_err(|x| format!("{id}: {x}"))?;
+
+        Ok(indexing_from_status(&value))
+    }
+
     /// Deletes the file with the given ID from open-webui.
     ///
     /// A `404` response is treated as success: the file is already
@@ -236,15 +510,18 @@ impl Server {
     ///
     /// This method returns an error if `id` is not a safe token or if
     /// the HTTP request fails with any status other than `404`.
-    pub fn delete_file(&self, id: &str) -> Result<(), String> {
+    fn delete_file(&self, id: &str) -> Result<(), String> {
         if !is_safe_id(id) {
             return Err(format!("unsafe file id {id:?}"));
         }
 
-        let uri = self.url(&format!("/api/v1/files/{id}"));
+        let uri = self.server.url(&format!("/api/v1/files/{id}"))?;
 
-        match ureq::delete(&uri)
-            .header("Authorization", &self.bearer())
+        match self
+            .server
+            .agent()?
+            .delete(&uri)
+            .header("Authorization", &self.server.bearer()?)
             .call()
         {
             Ok(_) => Ok(()),
@@ -262,9 +539,12 @@ impl Server {
     ///
     /// This method returns an error if the HTTP request fails or the
     /// response is not a JSON array.
-    pub fn list_files(&self) -> Result<Vec<String>, String> {
-        let value: Value = ureq::get(&self.url("/api/v1/files/"))
-            .header("Authorization", &self.bearer())
+    fn list_files(&self) -> Result<Vec<String>, String> {
+        let value: Value = self
+            .server
+            .agent()?
+            .get(&self.server.url("/api/v1/files/")?)
+            .header("Authorization", &self.server.bearer()?)
             .call()
             .map_err(|x| format!("{x}"))?
             .into_body()
@@ -289,6 +569,153 @@ impl Server {
 
         Ok(ids)
     }
+
+    /// Lists the models Open WebUI offers through `/api/models`, as the
+    /// raw JSON entries (see [`model_info`] for reading them).
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the HTTP request fails or the
+    /// response has no `data` array.
+    fn list_models(&self) -> Result<Vec<Value>, String> {
+        let value: Value = self
+            .server
+            .agent()?
+            .get(&self.server.url("/api/models")?)
+            .header("Authorization", &self.server.bearer()?)
+            .call()
+            .map_err(|x| format!("{x}"))?
+            .into_body()
+            .read_json()
+            .map_err(|x| format!("{x}"))?;
+
+        value["data"]
+            .as_array()
+            .cloned()
+            .ok_or_else(|| "malformed model list".to_string())
+    }
+
+    fn has_file_store(&self) -> bool {
+        true
+    }
+}
+
+/// What lui shows about a model from Open WebUI's `/api/models`.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ModelInfo {
+    pub id: String,
+    pub name: Option<String>,
+    pub owned_by: Option<String>,
+
+    /// The context window, from the model's `num_ctx` parameter.
+    pub context_length: Option<u64>,
+
+    /// Capabilities enabled for the model, e.g., `vision`, plus
+    /// `tools` if any tools are attached to it.
+    pub capabilities: Vec<String>,
+
+    /// The advanced parameters configured for the model in Open WebUI
+    /// (e.g., `num_ctx`, `temperature`).
+    pub params: serde_json::Map<String, Value>,
+}
+
+/// Reads one entry of the `/api/models` `data` array.
+///
+/// Like the `sources` array, this schema is undocumented and varies by
+/// provider, so every field is optional.  The ones read here (as of
+/// open-webui v0.9.6) are:
+///
+/// - `id`, `name`, and `owned_by` (e.g., `ollama` or `openai`),
+/// - `info.params`: the model's advanced parameters, including
+///   `num_ctx` if it was set in Open WebUI,
+/// - `info.meta.capabilities`: an object of boolean flags such as
+///   `vision` and `citations`, and
+/// - `info.meta.toolIds`: the tools attached to the model.
+pub fn model_info(model: &Value) -> ModelInfo {
+    let info = &model["info"];
+
+    let mut capabilities: Vec<String> = info["meta"]["capabilities"]
+        .as_object()
+        .map(|flags| {
+            flags
+                .iter()
+                .filter(|(_, enabled)| enabled.as_bool() == Some(true))
+                .map(|(name, _)| name.clone())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if info["meta"]["toolIds"]
+        .as_array()
+        .is_some_and(|ids| !ids.is_empty())
+    {
+        capabilities.push("tools".to_string());
+    }
+
+    let params =
+        info["params"].as_object().cloned().unwrap_or_default();
+
+    ModelInfo {
+        id: model["id"].as_str().unwrap_or("").to_string(),
+        name: model["name"].as_str().map(str::to_string),
+        owned_by: model["owned_by"].as_str().map(str::to_string),
+        context_length: params.get("num_ctx").and_then(Value::as_u64),
+        capabilities,
+        params,
+    }
+}
+
+/// Reads every certificate from the PEM file at `path`.
+///
+/// # Errors
+///
+/// This function returns an error if the file cannot be read, is not
+/// valid PEM, or holds no certificate.
+fn read_pem_certs(
+    path: &Path,
+) -> Result<Vec<Certificate<'static>>, String> {
+    let pem = std::fs::read(path)
+        .map_err(|x| format!("{}: {x}", path.to_string_lossy()))?;
+
+    let mut certs = Vec::new();
+
+    for item in ureq::tls::parse_pem(&pem) {
+        if let PemItem::Certificate(cert) = item
+            .map_err(|x| format!("{}: {x}", path.to_string_lossy()))?
+        {
+            certs.push(cert);
+        }
+    }
+
+    if certs.is_empty() {
+        return Err(format!(
+            "{}: no certificate found",
+            path.to_string_lossy()
+        ));
+    }
+
+    Ok(certs)
+}
+
+/// Reads the first private key from the PEM file at `path`.
+///
+/// # Errors
+///
+/// This function returns an error if the file cannot be read, is not
+/// valid PEM, or holds no private key.
+fn read_pem_key(path: &Path) -> Result<PrivateKey<'static>, String> {
+    let pem = std::fs::read(path)
+        .map_err(|x| format!("{}: {x}", path.to_string_lossy()))?;
+
+    for item in ureq::tls::parse_pem(&pem) {
+        if let PemItem::PrivateKey(key) = item
+            .map_err(|x| format!("{}: {x}", path.to_string_lossy()))?
+        {
+            return Ok(key);
+        }
+    }
+
+    Err(format!("{}: no private key found", path.to_string_lossy()))
 }
 
 /// Returns true if `id` is safe to use both as a single file path
@@ -303,7 +730,7 @@ impl Server {
 /// - The whole-string values `.` and `..` are rejected.
 /// - The ID cannot be longer than 255 bytes, to prevent issues on file
 ///   systems that don't support longer filenames.
-fn is_safe_id(id: &str) -> bool {
+pub fn is_safe_id(id: &str) -> bool {
     !id.is_empty()
         && id.len() <= 255
         && id != "."
@@ -316,16 +743,20 @@ fn is_safe_id(id: &str) -> bool {
         })
 }
 
-/// Derives the filename open-webui sees, with any character that would
-/// break the Content-Disposition header (quotes, backslashes, CR/LF and
-/// other control characters) removed.
-fn sanitize_filename(path: &Path) -> String {
-    let raw = path
-        .file_name()
-        .map(|x| x.to_string_lossy().into_owned())
-        .unwrap_or_else(|| "file".to_string());
-
-    let cleaned: String = raw
+/// Derives the filename open-webui sees from the upload `name` (see
+/// [`context::upload_name`]), with any character that would break the
+/// Content-Disposition header (quotes, backslashes, CR/LF and other
+/// control characters) removed.
+///
+/// Open WebUI keeps only the last component of an uploaded filename, so
+/// the directories of a relative name are joined with `__` instead of
+/// `/`: `docs/v1/api.md` becomes `docs__v1__api.md`.
+fn sanitize_filename(name: &str) -> String {
+    let cleaned: String = name
+        .split('/')
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join("__")
         .chars()
         .filter(|c| *c != '"' && *c != '\\' && !c.is_control())
         .collect();
@@ -337,39 +768,124 @@ fn sanitize_filename(path: &Path) -> String {
     }
 }
 
-/// Guesses a MIME type from the file extension so that open-webui can
-/// pick the right document loader.  Falls back to a generic binary type
-/// for unknown extensions.
-fn content_type_for(path: &Path) -> &'static str {
-    match path
-        .extension()
-        .and_then(|x| x.to_str())
-        .map(str::to_ascii_lowercase)
-        .as_deref()
-    {
-        Some("csv") => "text/csv",
-        Some("doc") => "application/msword",
-        Some("docx") => {
-            "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
-        }
-        Some("epub") => "application/epub+zip",
-        Some("html") | Some("htm") => "text/html",
-        Some("json") => "application/json",
-        Some("md") | Some("markdown") => "text/markdown",
-        Some("pdf") => "application/pdf",
-        Some("ppt") => "application/vnd.ms-powerpoint",
-        Some("pptx") => {
-            "application/vnd.openxmlformats-officedocument.presentationml.presentation"
+/// Reads the response of `/api/v1/files/{id}/process/status`:
+/// `{"status": "pending" | "completed" | "failed", "error": ...}`.  An
+/// unknown status counts as pending, so that the caller keeps polling
+/// until it times out.
+fn indexing_from_status(value: &Value) -> Indexing {
+    match value["status"].as_str() {
+        Some("completed") => Indexing::Done,
+        Some("failed") => Indexing::Failed(
+            value["error"]
+                .as_str()
+                .filter(|x| !x.is_empty())
+                .unwrap_or("indexing failed")
+                .to_string(),
+        ),
+        _ => Indexing::Pending,
+    }
+}
+
+/// A `multipart/form-data` body with a single `file` field, which reads
+/// the file as the body is sent instead of buffering it in memory.
+struct MultipartFile<R> {
+    /// The value of the `Content-Type` header, including the boundary.
+    content_type: String,
+    /// The length of the whole body, for the `Content-Length` header.
+    len: u64,
+    reader: Chain<Chain<Part, Exact<R>>, Part>,
+}
+
+/// The header or the trailer of a [`MultipartFile`].
+type Part = Cursor<Vec<u8>>;
+
+/// A reader that yields exactly as many bytes as its [`Take`] is
+/// limited to, and fails if the inner reader ends early.  Without the
+/// error, ureq would keep reading the missing bytes of the body that
+/// the `Content-Length` promised, and get none, forever.
+struct Exact<R>(Take<R>);
+
+impl<R: Read> Read for Exact<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.0.read(buf)?;
+
+        if read == 0 && !buf.is_empty() && self.0.limit() > 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "the file shrank while it was read",
+            ));
         }
-        Some("rst") => "text/x-rst",
-        Some("tsv") => "text/tab-separated-values",
-        Some("txt") | Some("text") | Some("log") => "text/plain",
-        Some("xml") => "application/xml",
-        Some("xls") => "application/vnd.ms-excel",
-        Some("xlsx") => {
-            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+
+        Ok(read)
+    }
+}
+
+impl MultipartFile<std::fs::File> {
+    /// Opens the RAG `file` for uploading.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the file cannot be opened or
+    /// its size cannot be read.
+    fn open(file: &RagFile) -> Result<Self, String> {
+        let error = |x: std::io::Error| format!("{}: {x}", file.label);
+
+        let content = std::fs::File::open(&file.path).map_err(error)?;
+        let len = content.metadata().map_err(error)?.len();
+
+        Ok(Self::new(
+            content,
+            len,
+            &sanitize_filename(&file.name),
+            context::rag_type(&file.path, &file.label)?.mime,
+        ))
+    }
+}
+
+impl<R: Read> MultipartFile<R> {
+    /// Wraps the `len` bytes that `file` yields, as the file `filename`
+    /// of type `content_type`.
+    ///
+    /// The body is exactly as long as [`len`](Self::len) says: a file
+    /// that grows while it is read is cut off, and one that shrinks
+    /// makes the request fail rather than send a malformed body.
+    fn new(
+        file: R,
+        len: u64,
+        filename: &str,
+        content_type: &str,
+    ) -> Self {
+        // The filename is interpolated into a Content-Disposition
+        // header, so quotes and control characters (which a Unix
+        // filename may legally contain) must already be removed by
+        // `sanitize_filename` to avoid producing a malformed or
+        // injectable multipart body.
+        let boundary = multipart_boundary();
+
+        let head = format!(
+            "--{boundary}\r\n\
+             Content-Disposition: form-data; \
+             name=\"file\"; filename=\"{filename}\"\r\n\
+             Content-Type: {content_type}\r\n\r\n"
+        )
+        .into_bytes();
+        let tail = format!("\r\n--{boundary}--\r\n").into_bytes();
+
+        Self {
+            content_type: format!(
+                "multipart/form-data; boundary={boundary}"
+            ),
+            len: head.len() as u64 + len + tail.len() as u64,
+            reader: Cursor::new(head)
+                .chain(Exact(file.take(len)))
+                .chain(Cursor::new(tail)),
         }
-        _ => "application/octet-stream",
+    }
+}
+
+impl<R: Read> Read for MultipartFile<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
     }
 }
 
@@ -385,8 +901,39 @@ fn multipart_boundary() -> String {
     format!("----luiBoundary{}{}", std::process::id(), nanos)
 }
 
-/// Reads the complete output from open-webui for a non-streamed
-/// request.
+/// Wraps the response to a chat completion request in an
+/// `OutputReader`: a `TokenIter` over the server-sent events if
+/// `stream` is true, and the complete output otherwise.
+///
+/// # Errors
+///
+/// This function returns an error if the response is not streamed and
+/// cannot be read (see [`get_complete_output`]).
+pub fn read_output(
+    response: http::response::Response<ureq::Body>,
+    stream: bool,
+) -> Result<OutputReader<BodyReader<'static>>, String> {
+    if stream {
+        let body_reader = response.into_body().into_reader();
+
+        Ok(OutputReader::Streamed(TokenIter {
+            reader: BufReader::new(body_reader),
+            sources: Vec::new(),
+        }))
+    } else {
+        let (output, sources) = get_complete_output(response)?;
+
+        Ok(OutputReader::Complete(OutputIter {
+            output: Some(output),
+            sources,
+        }))
+    }
+}
+
+/// Reads the complete output for a non-streamed request.
+///
+/// `usage.approximate_total` is specific to Open WebUI and may be
+/// missing.
 ///
 /// # Errors
 ///
@@ -395,8 +942,7 @@ fn multipart_boundary() -> String {
 /// - not valid JSON,
 /// - doesn't contain a message field,
 /// - contains a non-integer prompt token count, or
-/// - contains a message or an approximate duration that is not valid
-///   UTF-8.
+/// - contains a message that is not valid UTF-8.
 fn get_complete_output(
     response: http::response::Response<ureq::Body>,
 ) -> Result<(Output, Vec<Value>), String> {
@@ -415,12 +961,9 @@ fn get_complete_output(
                 || "usage.prompt_tokens is not integer".to_string(),
             )?,
         ),
-        approximate_total: Some(
-            value["usage"]["approximate_total"]
-                .as_str()
-                .ok_or_else(|| "malformed response".to_string())?
-                .to_string(),
-        ),
+        approximate_total: value["usage"]["approximate_total"]
+            .as_str()
+            .map(str::to_owned),
     };
 
     // See Note [Open WebUI sources schema].
@@ -436,7 +979,7 @@ fn get_complete_output(
 /// If the context carries images, they are attached as `image_url`
 /// parts to the prompt's user message (the last message), turning its
 /// content from a plain string into a parts array.
-fn assemble_messages(
+pub fn assemble_messages(
     context: &Context,
     prompt: &Prompt,
 ) -> Vec<Message> {
@@ -476,25 +1019,90 @@ fn assemble_messages(
     messages
 }
 
+/// The fields of a [`Request`] that lui sets itself, and that a
+/// parameter must not name, lest the request have the field twice.
+pub const RESERVED_PARAMS: [&str; 6] = [
+    "model",
+    "messages",
+    "stream",
+    "response_format",
+    "stream_options",
+    "files",
+];
+
 #[derive(Debug, Serialize)]
-struct Request {
-    model: String,
-    messages: Vec<Message>,
-    stream: bool,
+pub struct Request {
+    pub model: String,
+    pub messages: Vec<Message>,
+    pub stream: bool,
+
+    /// Generation parameters (e.g., `temperature`), sent as top-level
+    /// fields the way the OpenAI API expects them.  They must not be
+    /// among the [`RESERVED_PARAMS`].
+    #[serde(flatten)]
+    pub params: Params,
+
+    /// Asks for JSON that matches a schema (see [`crate::schema`]).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<Value>,
+
+    /// Asks an OpenAI-compatible server to report token usage in the
+    /// last chunk of a streamed response.  Open WebUI does so anyway.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
 
     /// RAG file references.  Skipped entirely when empty so that
     /// non-RAG requests serialize exactly as they did before.
     #[serde(skip_serializing_if = "Vec::is_empty")]
-    files: Vec<FileRef>,
+    pub files: Vec<FileRef>,
 }
 
-/// A reference to a file that open-webui has already ingested, sent in
-/// the chat request so the server retrieves from it.
 #[derive(Debug, Serialize)]
-struct FileRef {
+pub struct StreamOptions {
+    pub include_usage: bool,
+}
+
+/// A reference to a file or a knowledge collection that open-webui has
+/// already ingested, sent in the chat request so the server retrieves
+/// from it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileRef {
     #[serde(rename = "type")]
     kind: String,
     id: String,
+    /// `full` asks open-webui to give the model the whole extracted
+    /// text of the file rather than the chunks that retrieval picks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<String>,
+}
+
+impl FileRef {
+    /// A file uploaded for RAG (`-r`).
+    pub fn file(id: &str) -> Self {
+        Self {
+            kind: "file".to_string(),
+            id: id.to_string(),
+            context: None,
+        }
+    }
+
+    /// Asks for the whole file to be given to the model
+    /// (`--rag-full`).
+    pub fn in_full(self) -> Self {
+        Self {
+            context: Some("full".to_string()),
+            ..self
+        }
+    }
+
+    /// A knowledge collection (`--knowledge`).
+    pub fn collection(id: &str) -> Self {
+        Self {
+            kind: "collection".to_string(),
+            id: id.to_string(),
+            context: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
@@ -579,6 +1187,7 @@ where
 {
     Complete(OutputIter),
     Streamed(TokenIter<T>),
+    Chunked(ChunkIter<T>),
 }
 
 impl<T> OutputReader<T>
@@ -597,6 +1206,7 @@ where
         match self {
             OutputReader::Complete(output_iter) => &output_iter.sources,
             OutputReader::Streamed(token_iter) => &token_iter.sources,
+            OutputReader::Chunked(_) => &[],
         }
     }
 }
@@ -615,6 +1225,9 @@ where
             OutputReader::Streamed(token_iter) => {
                 TokenIter::next(token_iter)
             }
+            OutputReader::Chunked(chunk_iter) => {
+                ChunkIter::next(chunk_iter)
+            }
         }
     }
 }
@@ -917,12 +1530,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn request_flattens_params() {
+        let request = Request {
+            model: "m".to_string(),
+            messages: Vec::new(),
+            stream: false,
+            params: Params::from([
+                ("seed".to_string(), serde_json::json!(42)),
+                ("temperature".to_string(), serde_json::json!(0)),
+            ]),
+            response_format: None,
+            stream_options: None,
+            files: Vec::new(),
+        };
+
+        let json = serde_json::to_string(&request).unwrap();
+
+        assert!(
+            json.contains(r#""seed":42,"temperature":0"#),
+            "params should be top-level fields: {json}"
+        );
+    }
+
     #[test]
     fn request_omits_files_when_empty() {
         let request = Request {
             model: "m".to_string(),
             messages: Vec::new(),
             stream: false,
+            params: Params::new(),
+            response_format: None,
+            stream_options: None,
             files: Vec::new(),
         };
 
@@ -940,20 +1579,199 @@ mod tests {
             model: "m".to_string(),
             messages: Vec::new(),
             stream: false,
-            files: vec![FileRef {
-                kind: "file".to_string(),
-                id: "abc123".to_string(),
-            }],
+            params: Params::new(),
+            response_format: None,
+            stream_options: None,
+            files: vec![
+                FileRef::file("abc123"),
+                FileRef::collection("kb1"),
+                FileRef::file("def456").in_full(),
+            ],
         };
 
         let json = serde_json::to_string(&request).unwrap();
 
         assert!(
-            json.contains(r#""files":[{"type":"file","id":"abc123"}]"#),
+            json.contains(
+                r#""files":[{"type":"file","id":"abc123"},{"type":"collection","id":"kb1"},{"type":"file","id":"def456","context":"full"}]"#
+            ),
             "unexpected files serialization: {json}"
         );
     }
 
+    fn test_server() -> Server {
+        Server {
+            host: Some("127.0.0.1".to_string()),
+            port: Some(3000),
+            api_key: Some(Secret::new("secret".to_string())),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn api_key_requires_exactly_one_source() {
+        let mut server = test_server();
+        assert_eq!(server.bearer().unwrap(), "Bearer secret");
+
+        let mut server_with_two = test_server();
+        server_with_two.api_key_env = Some("LUI_TEST_KEY".to_string());
+        assert!(server_with_two.bearer().is_err());
+
+        server.api_key = None;
+        server.resolved_api_key = OnceLock::new();
+        assert!(server.bearer().is_err());
+    }
+
+    #[test]
+    fn api_key_command_runs_once() {
+        // The command fails if it is run a second time, so a cached key
+        // is the only way the second call can succeed.
+        let marker = std::env::temp_dir()
+            .join(format!("lui-api-key-once-{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+
+        let server = Server {
+            api_key: None,
+            api_key_command: Some(format!(
+                "test ! -e {0} && touch {0} && echo sk-1",
+                marker.to_string_lossy()
+            )),
+            ..test_server()
+        };
+
+        assert_eq!(server.bearer().unwrap(), "Bearer sk-1");
+        assert_eq!(server.bearer().unwrap(), "Bearer sk-1");
+
+        let _ = std::fs::remove_file(&marker);
+    }
+
+    #[test]
+    fn url_uses_scheme_from_tls_setting() {
+        let mut server = test_server();
+        assert_eq!(
+            server.url("/api/v1/files/").unwrap(),
+            "http://127.0.0.1:3000/api/v1/files/"
+        );
+
+        server.tls = true;
+        assert_eq!(
+            server.url("/api/v1/files/").unwrap(),
+            "https://127.0.0.1:3000/api/v1/files/"
+        );
+    }
+
+    #[test]
+    fn url_brackets_ipv6_hosts() {
+        let mut server = test_server();
+
+        server.host = Some("::1".to_string());
+        assert_eq!(
+            server.url("/api/chat/completions").unwrap(),
+            "http://[::1]:3000/api/chat/completions"
+        );
+
+        // An already-bracketed literal is left alone.
+        server.host = Some("[fe80::1]".to_string());
+        assert_eq!(
+            server.url("/api/chat/completions").unwrap(),
+            "http://[fe80::1]:3000/api/chat/completions"
+        );
+    }
+
+    #[test]
+    fn url_joins_paths_onto_base_url_prefix() {
+        let mut server = test_server();
+        server.host = None;
+        server.port = None;
+
+        // With or without a trailing slash, the prefix is kept and no
+        // double slash is produced.
+        for base_url in
+            ["https://tools.corp/webui/", "https://tools.corp/webui"]
+        {
+            server.base_url = Some(base_url.to_string());
+            assert_eq!(
+                server.url("/api/v1/files/abc").unwrap(),
+                "https://tools.corp/webui/api/v1/files/abc"
+            );
+        }
+
+        server.base_url = Some("tools.corp/webui".to_string());
+        assert!(server.url("/api/v1/files/").is_err());
+    }
+
+    #[test]
+    fn url_requires_exactly_one_address_form() {
+        let mut server = test_server();
+        server.base_url = Some("https://tools.corp/".to_string());
+        assert!(server.url("/").is_err());
+
+        server.base_url = None;
+        server.port = None;
+        assert!(server.url("/").is_err());
+    }
+
+    #[test]
+    fn read_pem_rejects_files_without_pem_items() {
+        // Cargo.toml is readable but holds no PEM certificate or key.
+        assert_eq!(
+            read_pem_certs(Path::new("Cargo.toml")).unwrap_err(),
+            "Cargo.toml: no certificate found"
+        );
+        assert_eq!(
+            read_pem_key(Path::new("Cargo.toml")).unwrap_err(),
+            "Cargo.toml: no private key found"
+        );
+    }
+
+    #[test]
+    fn model_info_reads_open_webui_model_entry() {
+        use serde_json::json;
+
+        let model = json!({
+            "id": "gemma3:27b",
+            "name": "Gemma 3",
+            "owned_by": "ollama",
+            "info": {
+                "params": {"num_ctx": 16384, "temperature": 0.2},
+                "meta": {
+                    "capabilities": {
+                        "vision": true,
+                        "citations": true,
+                        "web_search": false,
+                    },
+                    "toolIds": ["calculator"],
+                },
+            },
+        });
+
+        let info = model_info(&model);
+
+        assert_eq!(info.id, "gemma3:27b");
+        assert_eq!(info.owned_by.as_deref(), Some("ollama"));
+        assert_eq!(info.context_length, Some(16384));
+        assert_eq!(
+            info.capabilities,
+            vec!["citations", "vision", "tools"]
+        );
+        assert_eq!(info.params["temperature"], json!(0.2));
+    }
+
+    #[test]
+    fn model_info_tolerates_missing_fields() {
+        use serde_json::json;
+
+        // An OpenAI-style entry without Open WebUI's `info` object.
+        let info =
+            model_info(&json!({"id": "gpt-4o", "owned_by": "openai"}));
+
+        assert_eq!(info.id, "gpt-4o");
+        assert_eq!(info.name, None);
+        assert_eq!(info.context_length, None);
+        assert!(info.capabilities.is_empty());
+        assert!(info.params.is_empty());
+    }
+
     #[test]
     fn is_safe_id_rejects_path_and_url_tricks() {
         // A real open-webui ID (UUID with hyphens) is accepted.
@@ -982,49 +1800,80 @@ mod tests {
 
     #[test]
     fn sanitize_filename_strips_header_breakers() {
+        assert_eq!(sanitize_filename("ev\"il\r\n.pdf"), "evil.pdf");
+        // The directories of a relative name are kept.
         assert_eq!(
-            sanitize_filename(Path::new("ev\"il\r\n.pdf")),
-            "evil.pdf"
-        );
-        assert_eq!(
-            sanitize_filename(Path::new("/tmp/report.pdf")),
-            "report.pdf"
+            sanitize_filename("docs/v1/api.md"),
+            "docs__v1__api.md"
         );
         // A name made entirely of stripped characters falls back.
-        assert_eq!(sanitize_filename(Path::new("\"\"")), "file");
+        assert_eq!(sanitize_filename("\"\""), "file");
     }
 
     #[test]
-    fn content_type_for_maps_common_extensions() {
-        assert_eq!(
-            content_type_for(Path::new("a.pdf")),
-            "application/pdf"
-        );
-        // Case-insensitive on the extension.
+    fn indexing_from_status_reads_the_processing_status() {
+        use serde_json::json;
+
         assert_eq!(
-            content_type_for(Path::new("A.PDF")),
-            "application/pdf"
+            indexing_from_status(&json!({"status": "completed"})),
+            Indexing::Done
         );
         assert_eq!(
-            content_type_for(Path::new("sheet.xlsx")),
-            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+            indexing_from_status(&json!({"status": "pending"})),
+            Indexing::Pending
         );
         assert_eq!(
-            content_type_for(Path::new("deck.pptx")),
-            "application/vnd.openxmlformats-officedocument.presentationml.presentation"
+            indexing_from_status(&json!({
+                "status": "failed",
+                "error": "No text could be extracted",
+            })),
+            Indexing::Failed("No text could be extracted".to_string())
         );
         assert_eq!(
-            content_type_for(Path::new("book.epub")),
-            "application/epub+zip"
+            indexing_from_status(&json!({"status": "failed"})),
+            Indexing::Failed("indexing failed".to_string())
         );
-        // Extensionless filename falls back to a generic binary type.
+    }
+
+    #[test]
+    fn multipart_file_streams_a_body_of_the_stated_length() {
+        let file = std::io::Cursor::new(b"hello, world".to_vec());
+        let mut body =
+            MultipartFile::new(file, 5, "a.txt", "text/plain");
+
+        let boundary = body
+            .content_type
+            .strip_prefix("multipart/form-data; boundary=")
+            .unwrap()
+            .to_string();
+
+        let mut bytes = Vec::new();
+        body.read_to_end(&mut bytes).unwrap();
+
         assert_eq!(
-            content_type_for(Path::new("notes")),
-            "application/octet-stream"
+            String::from_utf8(bytes.clone()).unwrap(),
+            format!(
+                "--{boundary}\r\n\
+                 Content-Disposition: form-data; name=\"file\"; \
+                 filename=\"a.txt\"\r\n\
+                 Content-Type: text/plain\r\n\r\n\
+                 hello\r\n--{boundary}--\r\n"
+            )
         );
+        assert_eq!(body.len, bytes.len() as u64);
+    }
+
+    #[test]
+    fn multipart_file_fails_if_the_file_is_short() {
+        let file = std::io::Cursor::new(b"hello".to_vec());
+        let mut body =
+            MultipartFile::new(file, 12, "a.txt", "text/plain");
+
+        let mut bytes = Vec::new();
+
         assert_eq!(
-            content_type_for(Path::new("archive.tar.gz")),
-            "application/octet-stream"
+            body.read_to_end(&mut bytes).unwrap_err().kind(),
+            std::io::ErrorKind::UnexpectedEof
         );
     }
 
@@ -1074,6 +1923,8 @@ mod tests {
             system: None,
             question: "foo".to_string(),
             model: Some("bar".to_string()),
+            params: Params::new(),
+            schema: None,
         }
     }
 
@@ -1173,6 +2024,8 @@ mod tests {
             system: Some("be brief".to_string()),
             question: "q".to_string(),
             model: Some("m".to_string()),
+            params: Params::new(),
+            schema: None,
         };
 
         let messages = assemble_messages(&context, &prompt);
@@ -0,0 +1,557 @@
+//! Persistent conversations (`--continue` and `--session`).
+//!
+//! Every exchange (the question, what was sent as context, the answer,
+//! and the model) is saved to a session file, one per conversation, in
+//! [`sessions_dir`], unless the run is left out with `--no-session`.
+//! A later run that continues the session sends the earlier questions
+//! and answers as history, so a follow-up question doesn't need them
+//! copied into `-H` by hand.
+//!
+//! Only the names of the context files and RAG uploads are kept, not
+//! their contents.  They are not sent again: the earlier answers carry
+//! what the model made of them.
+
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::prompt::{Prompt, question_message};
+use crate::server::{Message, MessageContent};
+
+/// The file name extension of session files.
+const EXTENSION: &str = "json";
+
+/// Returns the directory in which sessions are stored:
+/// `$XDG_STATE_HOME/lui/sessions`, or `$HOME/.local/state/lui/sessions`
+/// if `XDG_STATE_HOME` is unset or not an absolute path.
+///
+/// Returns `None` if neither can be determined.
+pub fn sessions_dir() -> Option<PathBuf> {
+    let mut path = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|x| x.is_absolute())
+        .or_else(|| {
+            std::env::home_dir().map(|x| x.join(".local").join("state"))
+        })?;
+
+    path.push("lui");
+    path.push("sessions");
+
+    Some(path)
+}
+
+/// One question and its answer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Exchange {
+    /// When the answer was received, in seconds since the Unix epoch.
+    pub time: u64,
+    pub model: Option<String>,
+    pub system: Option<String>,
+    pub question: String,
+
+    /// The labels of the context sent with the question (see
+    /// [`crate::context::Context::manifest`]).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub context: Vec<String>,
+
+    /// The names of the files uploaded for RAG.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rag: Vec<String>,
+
+    pub answer: String,
+}
+
+impl Exchange {
+    /// Records the answer to `prompt`, received now.
+    pub fn new(
+        prompt: &Prompt,
+        context: Vec<String>,
+        rag: Vec<String>,
+        answer: &str,
+    ) -> Self {
+        Self {
+            time: now(),
+            model: prompt.model.clone(),
+            system: prompt.system.clone(),
+            question: prompt.question.clone(),
+            context,
+            rag,
+            answer: answer.to_string(),
+        }
+    }
+}
+
+/// A conversation, and where it is stored.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    #[serde(skip)]
+    pub name: String,
+
+    /// The directory the session is stored in, or `None` if it is not
+    /// stored at all (see [`Session::unsaved`]).
+    #[serde(skip)]
+    dir: Option<PathBuf>,
+
+    pub exchanges: Vec<Exchange>,
+}
+
+impl Session {
+    /// Starts a new session in `dir`, named after the current time
+    /// (e.g., `20261017-093015`).  Nothing is written until
+    /// [`Session::save`].
+    pub fn create(dir: &Path) -> Self {
+        let base = time_name();
+        let mut name = base.clone();
+        let mut suffix = 1;
+
+        while session_path(dir, &name).exists() {
+            suffix += 1;
+            name = format!("{base}-{suffix}");
+        }
+
+        Self {
+            name,
+            dir: Some(dir.to_path_buf()),
+            exchanges: Vec::new(),
+        }
+    }
+
+    /// Starts a new session that is only kept in memory, e.g., for the
+    /// turns of `--chat` with `--no-session`.  [`Session::save`] does
+    /// nothing for it.
+    pub fn unsaved() -> Self {
+        Self {
+            name: time_name(),
+            dir: None,
+            exchanges: Vec::new(),
+        }
+    }
+
+    /// Starts a new session where this one is stored, if it is.
+    pub fn restart(&self) -> Self {
+        match self.dir {
+            Some(ref dir) => Self::create(dir),
+            None => Self::unsaved(),
+        }
+    }
+
+    /// Opens the session called `name` in `dir`, or starts it if there
+    /// is no such session yet.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the name is not valid (see
+    /// [`validate_name`]) or the session file cannot be read or parsed.
+    pub fn open(dir: &Path, name: &str) -> Result<Self, String> {
+        validate_name(name)?;
+
+        let path = session_path(dir, name);
+
+        let mut session = match std::fs::read_to_string(&path) {
+            Ok(x) => serde_json::from_str::<Self>(&x).map_err(|x| {
+                format!("{}: {x}", path.to_string_lossy())
+            })?,
+            Err(x) if x.kind() == std::io::ErrorKind::NotFound => {
+                Self {
+                    name: String::new(),
+                    dir: None,
+                    exchanges: Vec::new(),
+                }
+            }
+            Err(x) => {
+                return Err(format!("{}: {x}", path.to_string_lossy()));
+            }
+        };
+
+        session.name = name.to_string();
+        session.dir = Some(dir.to_path_buf());
+
+        Ok(session)
+    }
+
+    /// Opens the session in `dir` with the most recent exchange.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if there is no session or the
+    /// sessions cannot be read.
+    pub fn last(dir: &Path) -> Result<Self, String> {
+        list(dir)?
+            .into_iter()
+            .max_by_key(Session::updated)
+            .ok_or_else(|| {
+                "there is no session to continue".to_string()
+            })
+    }
+
+    /// Whether the session is stored, rather than only kept in memory.
+    pub fn is_saved(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// When the last exchange took place, in seconds since the Unix
+    /// epoch, or 0 if there was none.
+    pub fn updated(&self) -> u64 {
+        self.exchanges.last().map_or(0, |x| x.time)
+    }
+
+    /// The questions and answers so far, as the messages that replay
+    /// them.
+    pub fn history(&self) -> Vec<Message> {
+        self.exchanges
+            .iter()
+            .flat_map(|x| {
+                [
+                    question_message(&x.question),
+                    Message {
+                        role: "assistant".to_string(),
+                        content: MessageContent::Text(x.answer.clone()),
+                    },
+                ]
+            })
+            .collect()
+    }
+
+    /// `prompt`, with the questions and answers so far added to its
+    /// history.
+    pub fn continue_prompt(&self, prompt: &Prompt) -> Prompt {
+        let mut history = prompt.history.clone().unwrap_or_default();
+
+        history.extend(self.history());
+
+        Prompt {
+            history: (!history.is_empty()).then_some(history),
+            ..prompt.clone()
+        }
+    }
+
+    /// Writes the session to its file, creating the directory if
+    /// necessary.  The file is replaced in one step, so a concurrent
+    /// reader never sees it half-written.  Does nothing for a session
+    /// that is [not stored](Session::unsaved).
+    ///
+    /// The file holds the questions and answers, so only the user can
+    /// read it, and the directory, if this creates it.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the directory cannot be created
+    /// or the file cannot be written.
+    pub fn save(&self) -> Result<(), String> {
+        let Some(ref dir) = self.dir else {
+            return Ok(());
+        };
+
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+
+            builder.mode(0o700);
+        }
+
+        builder
+            .create(dir)
+            .map_err(|x| format!("{}: {x}", dir.to_string_lossy()))?;
+
+        let path = session_path(dir, &self.name);
+        let temporary = path.with_extension(format!(
+            "{EXTENSION}.{}.tmp",
+            std::process::id()
+        ));
+
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|x| x.to_string())?;
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+
+            options.mode(0o600);
+        }
+
+        options
+            .open(&temporary)
+            .and_then(|mut x| x.write_all(content.as_bytes()))
+            .and_then(|()| std::fs::rename(&temporary, &path))
+            .map_err(|x| format!("{}: {x}", path.to_string_lossy()))
+    }
+
+    /// The conversation as Markdown: each question, with the names of
+    /// its context, and each answer under a heading.
+    pub fn transcript(&self) -> String {
+        let mut result = String::new();
+
+        for exchange in &self.exchanges {
+            result.push_str(&format!(
+                "## user ({}, {})\n\n",
+                format_time(exchange.time),
+                exchange.model.as_deref().unwrap_or("-")
+            ));
+
+            for label in &exchange.context {
+                result.push_str(&format!("- context: {label}\n"));
+            }
+
+            for name in &exchange.rag {
+                result.push_str(&format!("- rag: {name}\n"));
+            }
+
+            if !exchange.context.is_empty() || !exchange.rag.is_empty()
+            {
+                result.push('\n');
+            }
+
+            result.push_str(&format!(
+                "{}\n\n## assistant\n\n{}\n\n",
+                exchange.question.trim_end(),
+                exchange.answer.trim_end()
+            ));
+        }
+
+        result
+    }
+}
+
+/// Reads every session in `dir`.  A missing directory yields an empty
+/// list.  A session file that cannot be read or parsed is skipped with
+/// a warning, so that it doesn't hide the other sessions.
+///
+/// # Errors
+///
+/// This function returns an error if the directory exists but cannot be
+/// read.
+pub fn list(dir: &Path) -> Result<Vec<Session>, String> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(x) => x,
+        Err(x) if x.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Vec::new());
+        }
+        Err(x) => {
+            return Err(format!("{}: {x}", dir.to_string_lossy()));
+        }
+    };
+
+    let mut result = Vec::new();
+
+    for entry in entries {
+        let path = entry
+            .map_err(|x| format!("{}: {x}", dir.to_string_lossy()))?
+            .path();
+
+        if path.extension().and_then(|x| x.to_str()) != Some(EXTENSION)
+        {
+            continue;
+        }
+
+        if let Some(name) = path.file_stem().and_then(|x| x.to_str()) {
+            match Session::open(dir, name) {
+                Ok(x) => result.push(x),
+                Err(x) => log::warn!("skipping a session: {x}"),
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// Deletes the session called `name` in `dir`.
+///
+/// # Errors
+///
+/// This function returns an error if the name is not valid, there is
+/// no such session, or its file cannot be removed.
+pub fn remove(dir: &Path, name: &str) -> Result<(), String> {
+    validate_name(name)?;
+
+    match std::fs::remove_file(session_path(dir, name)) {
+        Ok(()) => Ok(()),
+        Err(x) if x.kind() == std::io::ErrorKind::NotFound => {
+            Err(format!("session '{name}' not found"))
+        }
+        Err(x) => Err(format!("session '{name}': {x}")),
+    }
+}
+
+/// Checks that `name` can be used as a file name: it must not be empty,
+/// contain a path separator, or start with a dot.
+///
+/// # Errors
+///
+/// This function returns an error describing what is wrong with the
+/// name.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty()
+        || name.starts_with('.')
+        || name.contains(['/', '\\', '\0'])
+    {
+        return Err(format!(
+            "invalid session name '{name}': it must not be empty, start \
+             with '.', or contain '/'"
+        ));
+    }
+
+    Ok(())
+}
+
+fn session_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{name}.{EXTENSION}"))
+}
+
+/// The name of a session started now (e.g., `20261017-093015`).
+fn time_name() -> String {
+    format_time(now()).replace(['-', ':'], "").replace(' ', "-")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs())
+}
+
+/// Formats `time`, in seconds since the Unix epoch, as `YYYY-MM-DD
+/// HH:MM:SS` in UTC.
+pub fn format_time(time: u64) -> String {
+    let days = time / 86_400;
+    let seconds = time % 86_400;
+
+    // Howard Hinnant's `civil_from_days`, for days since 1970-01-01.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460
+        + day_of_era / 36_524
+        - day_of_era / 146_096)
+        / 365;
+    let day_of_year = day_of_era
+        - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temporary_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "lui-session-test-{name}-{}",
+            std::process::id()
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+
+        dir
+    }
+
+    fn exchange(time: u64, question: &str, answer: &str) -> Exchange {
+        Exchange {
+            time,
+            model: Some("m".to_string()),
+            system: None,
+            question: question.to_string(),
+            context: vec!["src/main.rs".to_string()],
+            rag: Vec::new(),
+            answer: answer.to_string(),
+        }
+    }
+
+    #[test]
+    fn format_time_is_utc_calendar_time() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00");
+        assert_eq!(format_time(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(format_time(1_792_229_415), "2026-10-17 09:30:15");
+    }
+
+    #[test]
+    fn validate_name_rejects_paths() {
+        assert!(validate_name("refactor-2").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("../x").is_err());
+        assert!(validate_name(".hidden").is_err());
+    }
+
+    #[test]
+    fn sessions_are_saved_listed_and_removed() {
+        let dir = temporary_dir("save");
+
+        assert!(Session::last(&dir).is_err());
+
+        let mut older = Session::open(&dir, "older").unwrap();
+        older.exchanges.push(exchange(100, "Who?", "Ada."));
+        older.save().unwrap();
+
+        let mut newer = Session::open(&dir, "newer").unwrap();
+        newer.exchanges.push(exchange(200, "What?", "Engines."));
+        newer.save().unwrap();
+
+        // Only the user can read the questions and answers.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = |x: &Path| {
+                std::fs::metadata(x).unwrap().permissions().mode()
+                    & 0o777
+            };
+
+            assert_eq!(mode(&dir), 0o700);
+            assert_eq!(mode(&session_path(&dir, "newer")), 0o600);
+        }
+
+        // A corrupt session file doesn't hide the others.
+        std::fs::write(dir.join("broken.json"), "{").unwrap();
+
+        let last = Session::last(&dir).unwrap();
+        assert_eq!(last.name, "newer");
+        assert_eq!(last.exchanges, newer.exchanges);
+
+        assert_eq!(list(&dir).unwrap().len(), 2);
+
+        remove(&dir, "newer").unwrap();
+        assert!(remove(&dir, "newer").is_err());
+        assert_eq!(Session::last(&dir).unwrap().name, "older");
+
+        // A session started anew where an unsaved one was is not
+        // stored either.
+        let mut unsaved = Session::unsaved().restart();
+        unsaved.exchanges.push(exchange(300, "Why?", "Because."));
+        unsaved.save().unwrap();
+        assert!(!unsaved.is_saved());
+        assert_eq!(list(&dir).unwrap().len(), 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn history_replays_questions_and_answers() {
+        let dir = temporary_dir("history");
+        let mut session = Session::create(&dir);
+        session.exchanges.push(exchange(100, "Who?", "Ada."));
+
+        let history = session.history();
+
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0], question_message("Who?"));
+        assert_eq!(history[1].role, "assistant");
+        assert_eq!(
+            history[1].content,
+            MessageContent::Text("Ada.".to_string())
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Rough token counts for checking a request against the model's
+//! context window before it is sent.
+//!
+//! Lui doesn't know the model's tokenizer, so it estimates instead:
+//! about four characters per token, which is close for English prose
+//! and code with the tokenizers of common open models.  The estimate
+//! only needs to be good enough to catch a context that is clearly too
+//! large, which is the case the model would otherwise silently ignore.
+
+use crate::context::Context;
+use crate::server::{ContentPart, Message, MessageContent};
+
+/// Characters per token assumed by the estimate.
+const CHARS_PER_TOKEN: u64 = 4;
+
+/// Tokens added for each message by the chat template (role markers
+/// and separators).
+const TOKENS_PER_MESSAGE: u64 = 4;
+
+/// Tokens assumed for each image.  The real cost depends on the vision
+/// encoder (e.g., 256 for Gemma 3, more for Llama 3.2 Vision), so this
+/// errs on the high side.
+const TOKENS_PER_IMAGE: u64 = 1024;
+
+/// Estimates the number of tokens in `text`.
+pub fn estimate_text(text: &str) -> u64 {
+    (text.chars().count() as u64).div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Estimates the number of prompt tokens that `messages` take up.
+pub fn estimate_messages(messages: &[Message]) -> u64 {
+    messages
+        .iter()
+        .map(|message| {
+            TOKENS_PER_MESSAGE
+                + match &message.content {
+                    MessageContent::Text(text) => estimate_text(text),
+                    MessageContent::Parts(parts) => parts
+                        .iter()
+                        .map(|part| match part {
+                            ContentPart::Text { text } => {
+                                estimate_text(text)
+                            }
+                            ContentPart::ImageUrl { .. } => {
+                                TOKENS_PER_IMAGE
+                            }
+                        })
+                        .sum(),
+                }
+        })
+        .sum()
+}
+
+/// Estimates how many tokens each piece of the context contributes, and
+/// returns the pieces labeled and sorted by that estimate, largest
+/// first.
+pub fn context_contributions(context: &Context) -> Vec<(String, u64)> {
+    let mut result: Vec<(String, u64)> = Vec::new();
+
+    if let Some(ref content) = context.anonymous {
+        result.push(("(stdin)".to_string(), estimate_text(content)));
+    }
+
+    for (label, content) in &context.named {
+        result.push((label.clone(), estimate_text(content)));
+    }
+
+    for (label, _) in &context.images {
+        result.push((label.clone(), TOKENS_PER_IMAGE));
+    }
+
+    result.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_text_rounds_up() {
+        assert_eq!(estimate_text(""), 0);
+        assert_eq!(estimate_text("abc"), 1);
+        assert_eq!(estimate_text("abcd"), 1);
+        assert_eq!(estimate_text("abcde"), 2);
+        // Characters, not bytes.
+        assert_eq!(estimate_text("概括概括"), 1);
+    }
+
+    #[test]
+    fn estimate_messages_counts_overhead_and_images() {
+        let messages = vec![
+            Message {
+                role: "system".to_string(),
+                content: MessageContent::Text("x".repeat(40)),
+            },
+            Message {
+                role: "user".to_string(),
+                content: MessageContent::Parts(vec![
+                    ContentPart::Text {
+                        text: "y".repeat(8),
+                    },
+                    ContentPart::ImageUrl {
+                        image_url: crate::server::ImageUrl {
+                            url: "data:image/png;base64,AAA"
+                                .to_string(),
+                        },
+                    },
+                ]),
+            },
+        ];
+
+        assert_eq!(
+            estimate_messages(&messages),
+            (TOKENS_PER_MESSAGE + 10)
+                + (TOKENS_PER_MESSAGE + 2 + TOKENS_PER_IMAGE)
+        );
+    }
+
+    #[test]
+    fn context_contributions_sorts_largest_first() {
+        let mut context = Context::new();
+        context.named.push(("small.rs".to_string(), "x".repeat(8)));
+        context.named.push(("big.log".to_string(), "x".repeat(800)));
+        context.anonymous = Some("x".repeat(80));
+
+        let labels: Vec<String> = context_contributions(&context)
+            .into_iter()
+            .map(|(label, _)| label)
+            .collect();
+
+        assert_eq!(labels, vec!["big.log", "(stdin)", "small.rs"]);
+    }
+}